@@ -0,0 +1,121 @@
+//! Bytes per frame of the attack socket in each encoding, for a typical `MoveAttacker` request
+//! and its reply.
+//!
+//! Run with `cargo bench --bench socket_encoding`.
+
+use std::time::Instant;
+
+use aot_backend::api::attack::socket::{
+    ActionType, BuildingResponse, DefenderResponse, EncodedMessage, ResultType, SocketEncoding,
+    SocketRequest, SocketResponse,
+};
+use aot_backend::validator::util::Coords;
+
+const ITERATIONS: u32 = 100_000;
+
+fn move_request(path_length: i32) -> SocketRequest {
+    SocketRequest {
+        frame_number: 120,
+        action_type: ActionType::MoveAttacker,
+        attacker_id: Some(1),
+        attacker_instance_id: Some(0),
+        bomb_id: None,
+        start_position: Some(Coords { x: 10, y: 4 }),
+        attacker_path: (0..path_length)
+            .map(|step| Coords { x: 10 + step, y: 4 })
+            .collect(),
+        bomb_position: Coords { x: 0, y: 0 },
+        is_game_over: None,
+        bomb_loadout: None,
+        state_checksum: None,
+    }
+}
+
+fn move_response(is_eventful: bool) -> SocketResponse {
+    SocketResponse {
+        frame_number: 120,
+        result_type: if is_eventful {
+            ResultType::DefendersDamaged
+        } else {
+            ResultType::Nothing
+        },
+        attacker_instance_id: Some(0),
+        is_alive: Some(true),
+        attacker_health: Some(80),
+        exploded_mines: None,
+        defender_damaged: is_eventful.then(|| {
+            vec![DefenderResponse {
+                id: 3,
+                position: Coords { x: 12, y: 4 },
+                damage: 20,
+            }]
+        }),
+        hut_triggered: false,
+        hut_defenders: None,
+        damaged_buildings: is_eventful.then(|| {
+            vec![BuildingResponse {
+                id: 41,
+                position: Coords { x: 14, y: 6 },
+                hp: 250,
+                artifacts_if_damaged: 12,
+            }]
+        }),
+        total_damage_percentage: Some(12.5),
+        is_sync: false,
+        remaining_time_in_millis: None,
+        error_code: None,
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: Some(String::from("Movement Response")),
+        result: None,
+    }
+}
+
+fn encoded_len(message: EncodedMessage) -> usize {
+    match message {
+        EncodedMessage::Text(text) => text.len(),
+        EncodedMessage::Binary(bytes) => bytes.len(),
+    }
+}
+
+fn report<T: serde::Serialize>(name: &str, message: &T) {
+    let json = encoded_len(SocketEncoding::Json.encode(message).unwrap());
+    let msgpack = encoded_len(SocketEncoding::MessagePack.encode(message).unwrap());
+    println!(
+        "{:<28} json {:>5} B   msgpack {:>5} B   ({:.0}%)",
+        name,
+        json,
+        msgpack,
+        100.0 * msgpack as f64 / json as f64
+    );
+}
+
+fn time_encoding<T: serde::Serialize>(name: &str, encoding: SocketEncoding, message: &T) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        encoded_len(encoding.encode(message).unwrap());
+    }
+    println!(
+        "{:<28} {:?}: {:>6.0} ns/frame",
+        name,
+        encoding,
+        start.elapsed().as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    println!("bytes per frame");
+    report("request, 1 step", &move_request(1));
+    report("request, 5 steps", &move_request(5));
+    report("response, nothing", &move_response(false));
+    report("response, defenders", &move_response(true));
+
+    println!("\nencoding time");
+    let request = move_request(5);
+    let response = move_response(true);
+    for encoding in [SocketEncoding::Json, SocketEncoding::MessagePack] {
+        time_encoding("request, 5 steps", encoding, &request);
+        time_encoding("response, defenders", encoding, &response);
+    }
+}
@@ -64,59 +64,59 @@ COPY public.prop FROM stdin;
 \.
 
 
-COPY public.building_type FROM stdin;
-0	Road	1	1	0	0	0	0	0
-1	Bank	3	3	2147483647	1	10	120	0
-2	Building_2	4	4	120	1	10	140	0
-3	Building_3	5	5	140	1	10	160	0
-4	Building_4	3	3	90	1	10	110	0
-5	Building_5	4	4	110	1	10	130	0
-6	Building_6	5	5	130	1	10	150	0
-7	Building_7	3	3	80	1	10	100	0
-8	Building_8	4	4	100	1	10	120	0
-9	Building_9	5	5	120	1	10	140	0
-10	Building_10	3	3	70	1	10	90	0
-11	Building_11	4	4	90	1	10	110	0
-12	Building_12	5	5	110	1	10	130	0
-13	Building_13	3	3	60	1	10	80	0
-14	Building_14	4	4	80	1	10	100	0
-15	Building_15	5	5	100	1	10	120	0
-16	Bank	3	3	2147483647	2	50	140	0
-17	Building_2	4	4	130	2	75	160	0
-18	Building_3	5	5	150	2	100	180	0
-19	Building_4	3	3	100	2	25	120	0
-20	Building_5	4	4	120	2	50	140	0
-21	Building_6	5	5	140	2	75	160	0
-22	Building_7	3	3	90	2	20	110	0
-23	Building_8	4	4	110	2	45	130	0
-24	Building_9	5	5	130	2	70	150	0
-25	Building_10	3	3	80	2	15	100	0
-26	Building_11	4	4	100	2	40	120	0
-27	Building_12	5	5	120	2	65	140	0
-28	Building_13	3	3	70	2	10	90	0
-29	Building_14	4	4	90	2	35	110	0
-30	Building_15	5	5	110	2	60	130	0
-31	Bank	3	3	2147483647	3	-1	160	0
-32	Building_2	4	4	140	3	-1	180	0
-33	Building_3	5	5	160	3	-1	200	0
-34	Building_4	3	3	110	3	-1	140	0
-35	Building_5	4	4	130	3	-1	160	0
-36	Building_6	5	5	150	3	-1	180	0
-37	Building_7	3	3	100	3	-1	120	0
-38	Building_8	4	4	120	3	-1	140	0
-39	Building_9	5	5	140	3	-1	160	0
-40	Building_10	3	3	90	3	-1	110	0
-41	Building_11	4	4	110	3	-1	130	0
-42	Building_12	5	5	130	3	-1	150	0
-43	Building_13	3	3	80	3	-1	100	0
-44	Building_14	4	4	100	3	-1	120	0
-45	Building_15	5	5	120	3	-1	140	0
-46	Defender_Hut	3	3	120	1	10	120	13
-47	Defender_Hut	3	3	120	2	75	140	14
-48	Defender_Hut	3	3	120	3	-1	160	15
-49	Sentry	3	3	120	1	10	120	16
-50	Sentry	3	3	120	2	75	140	17
-51	Sentry	3	3	120	3	-1	160	18
+COPY public.building_type (id, name, width, height, capacity, level, cost, hp, prop_id, production_rate) FROM stdin;
+0	Road	1	1	0	0	0	0	0	0
+1	Bank	3	3	2147483647	1	10	120	0	0
+2	Building_2	4	4	120	1	10	140	0	0
+3	Building_3	5	5	140	1	10	160	0	0
+4	Building_4	3	3	90	1	10	110	0	0
+5	Building_5	4	4	110	1	10	130	0	0
+6	Building_6	5	5	130	1	10	150	0	0
+7	Building_7	3	3	80	1	10	100	0	0
+8	Building_8	4	4	100	1	10	120	0	0
+9	Building_9	5	5	120	1	10	140	0	0
+10	Building_10	3	3	70	1	10	90	0	0
+11	Building_11	4	4	90	1	10	110	0	0
+12	Building_12	5	5	110	1	10	130	0	0
+13	Building_13	3	3	60	1	10	80	0	10
+14	Building_14	4	4	80	1	10	100	0	0
+15	Building_15	5	5	100	1	10	120	0	0
+16	Bank	3	3	2147483647	2	50	140	0	0
+17	Building_2	4	4	130	2	75	160	0	0
+18	Building_3	5	5	150	2	100	180	0	0
+19	Building_4	3	3	100	2	25	120	0	0
+20	Building_5	4	4	120	2	50	140	0	0
+21	Building_6	5	5	140	2	75	160	0	0
+22	Building_7	3	3	90	2	20	110	0	0
+23	Building_8	4	4	110	2	45	130	0	0
+24	Building_9	5	5	130	2	70	150	0	0
+25	Building_10	3	3	80	2	15	100	0	0
+26	Building_11	4	4	100	2	40	120	0	0
+27	Building_12	5	5	120	2	65	140	0	0
+28	Building_13	3	3	70	2	10	90	0	20
+29	Building_14	4	4	90	2	35	110	0	0
+30	Building_15	5	5	110	2	60	130	0	0
+31	Bank	3	3	2147483647	3	-1	160	0	0
+32	Building_2	4	4	140	3	-1	180	0	0
+33	Building_3	5	5	160	3	-1	200	0	0
+34	Building_4	3	3	110	3	-1	140	0	0
+35	Building_5	4	4	130	3	-1	160	0	0
+36	Building_6	5	5	150	3	-1	180	0	0
+37	Building_7	3	3	100	3	-1	120	0	0
+38	Building_8	4	4	120	3	-1	140	0	0
+39	Building_9	5	5	140	3	-1	160	0	0
+40	Building_10	3	3	90	3	-1	110	0	0
+41	Building_11	4	4	110	3	-1	130	0	0
+42	Building_12	5	5	130	3	-1	150	0	0
+43	Building_13	3	3	80	3	-1	100	0	30
+44	Building_14	4	4	100	3	-1	120	0	0
+45	Building_15	5	5	120	3	-1	140	0	0
+46	Defender_Hut	3	3	120	1	10	120	13	0
+47	Defender_Hut	3	3	120	2	75	140	14	0
+48	Defender_Hut	3	3	120	3	-1	160	15	0
+49	Sentry	3	3	120	1	10	120	16	0
+50	Sentry	3	3	120	2	75	140	17	0
+51	Sentry	3	3	120	3	-1	160	18	0
 \.
 
 COPY public.defender_type FROM stdin;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+CREATE TABLE public.game_invalidation (
+	id serial NOT NULL,
+	game_id INTEGER NOT NULL,
+	reason TEXT NOT NULL,
+	frame_number INTEGER NOT NULL,
+	policy VARCHAR(255) NOT NULL,
+	CONSTRAINT game_invalidation_pk PRIMARY KEY (id),
+	CONSTRAINT game_invalidation_fk0 FOREIGN KEY (game_id) REFERENCES public.game(id)
+);
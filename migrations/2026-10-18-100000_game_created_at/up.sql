@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE public.game
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
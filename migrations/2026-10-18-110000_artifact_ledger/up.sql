@@ -0,0 +1,28 @@
+-- Your SQL goes here
+
+CREATE TABLE public.artifact_ledger (
+	id serial NOT NULL,
+	user_id INTEGER NOT NULL,
+	source_map_space_id INTEGER,
+	destination_map_space_id INTEGER,
+	amount INTEGER NOT NULL,
+	reason VARCHAR(255) NOT NULL,
+	game_id INTEGER,
+	request_id VARCHAR(255),
+	created_at TIMESTAMP NOT NULL DEFAULT now(),
+	CONSTRAINT artifact_ledger_pk PRIMARY KEY (id),
+	CONSTRAINT artifact_ledger_fk0 FOREIGN KEY (user_id) REFERENCES public.user(id),
+	CONSTRAINT artifact_ledger_fk1 FOREIGN KEY (game_id) REFERENCES public.game(id)
+);
+
+CREATE INDEX artifact_ledger_user_id_idx ON public.artifact_ledger (user_id, id);
+
+CREATE FUNCTION artifact_ledger_append_only() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'artifact_ledger is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER artifact_ledger_append_only
+BEFORE UPDATE OR DELETE ON public.artifact_ledger
+FOR EACH ROW EXECUTE FUNCTION artifact_ledger_append_only();
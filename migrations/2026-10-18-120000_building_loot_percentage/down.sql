@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE public.building_type
+DROP COLUMN loot_percentage;
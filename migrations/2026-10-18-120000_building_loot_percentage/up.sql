@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE public.building_type
+ADD COLUMN loot_percentage INTEGER NOT NULL DEFAULT 30
+CHECK (loot_percentage BETWEEN 0 AND 100);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE public.artifact_production;
+
+ALTER TABLE public.building_type
+DROP COLUMN production_rate;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+-- Artifacts a building of this type produces per hour; 0 for everything but producers
+ALTER TABLE public.building_type
+ADD COLUMN production_rate INTEGER NOT NULL DEFAULT 0
+CHECK (production_rate >= 0);
+
+CREATE TABLE public.artifact_production (
+	map_space_id INTEGER NOT NULL,
+	last_collected_at TIMESTAMP NOT NULL DEFAULT now(),
+	CONSTRAINT artifact_production_pk PRIMARY KEY (map_space_id),
+	CONSTRAINT artifact_production_fk0 FOREIGN KEY (map_space_id) REFERENCES public.map_spaces(id) ON DELETE CASCADE
+);
+
+-- Building_13 produces, more with each level
+UPDATE public.building_type
+SET production_rate = 10 * level
+WHERE name = 'Building_13';
+
+-- Producers already on a base start their clock now
+INSERT INTO public.artifact_production (map_space_id)
+SELECT map_spaces.id
+FROM public.map_spaces
+INNER JOIN public.block_type ON block_type.id = map_spaces.block_type_id
+INNER JOIN public.building_type ON building_type.id = block_type.building_type
+WHERE building_type.production_rate > 0;
@@ -0,0 +1,77 @@
+use super::attack::game_actor::{GameOutcome, GetGameSummary};
+use super::attack::registry::{EndGameById, GameRegistry, ListGames, LookupGame};
+use super::auth::session::AdminUser;
+use actix::Addr;
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::{web, HttpResponse, Responder, Result};
+use serde::Deserialize;
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/games").route(web::get().to(list_live_games)))
+        .service(web::resource("/games/{game_id}").route(web::get().to(get_live_game)))
+        .service(
+            web::resource("/games/{game_id}/terminate").route(web::post().to(terminate_live_game)),
+        );
+}
+
+#[derive(Deserialize)]
+struct TerminateRequest {
+    outcome: GameOutcome,
+}
+
+async fn list_live_games(
+    registry: web::Data<Addr<GameRegistry>>,
+    _admin: AdminUser,
+) -> Result<impl Responder> {
+    let games = registry
+        .send(ListGames)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(web::Json(games))
+}
+
+async fn get_live_game(
+    game_id: web::Path<i32>,
+    registry: web::Data<Addr<GameRegistry>>,
+    _admin: AdminUser,
+) -> Result<impl Responder> {
+    let game_id = game_id.into_inner();
+    let game = registry
+        .send(LookupGame { game_id })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or(ErrorNotFound("Game not in progress"))?;
+    let summary = game
+        .send(GetGameSummary)
+        .await
+        .map_err(|_| ErrorNotFound("Game not in progress"))?;
+    Ok(web::Json(summary))
+}
+
+async fn terminate_live_game(
+    game_id: web::Path<i32>,
+    request: web::Json<TerminateRequest>,
+    registry: web::Data<Addr<GameRegistry>>,
+    admin: AdminUser,
+) -> Result<impl Responder> {
+    let game_id = game_id.into_inner();
+    let outcome = request.into_inner().outcome;
+    log::info!(
+        "Admin:{} is terminating game:{} with outcome {:?}",
+        admin.0,
+        game_id,
+        outcome
+    );
+    let is_terminated = registry
+        .send(EndGameById {
+            game_id,
+            reason: "Terminated by admin".to_string(),
+            outcome,
+        })
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !is_terminated {
+        return Err(ErrorNotFound("Game not in progress"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,755 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_web::web::{self, Data};
+use actix_ws::Message as WsMessage;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::channel::oneshot;
+use futures_util::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::registry::{GameRegistry, RegisterGame, UnregisterGame};
+use super::socket::{
+    BuildingResponse, ClientMessage, EncodedMessage, HandshakeRequest, ResultType, SocketEncoding,
+    SocketErrorCode, SocketProtocol, SocketResponse,
+};
+use super::util::{self, GameLog};
+use crate::api::{PgPool, RedisPool};
+use crate::constants::{
+    CLOCK_SYNC_CAPABILITY, CLOCK_SYNC_INTERVAL_IN_SECONDS, FRAME_DURATION_IN_MILLIS,
+    GAME_AGE_IN_MINUTES, MAX_FRAMES_AHEAD_OF_CLOCK, RECONNECT_GRACE_IN_SECONDS,
+};
+use crate::models::AttackerType;
+use crate::validator::state::State;
+use crate::validator::util::{
+    send_clock_sync_message, send_error_message, send_resume_message, send_shutdown_message,
+    send_terminate_game_message, BombType, Coords, SourceDestXY,
+};
+use crate::validator::{batch_handler, game_handler};
+
+/// Everything loaded for a game before its socket is opened.
+pub struct GameSetup {
+    pub game_id: i32,
+    pub attacker_id: i32,
+    pub defender_id: i32,
+    pub game_session: i64,
+    pub started_at: i64, // unix millis at which the game clock started
+    pub is_resumed: bool,
+    pub state: State,
+    pub game_log: GameLog,
+    pub damaged_buildings: Vec<BuildingResponse>,
+    pub attacker_types: HashMap<i32, AttackerType>,
+    pub shortest_paths: HashMap<SourceDestXY, Coords>,
+    pub roads: HashSet<(i32, i32)>,
+    pub bomb_types: Vec<BombType>,
+}
+
+/// One live attack. It owns the game `State`, the match clock and the settlement of the game,
+/// and registers itself with the `GameRegistry` for as long as it runs.
+///
+/// The attacker's socket is pumped by two tasks in `socket_handler`: incoming frames arrive as
+/// `ClientFrame`s and responses leave through `outgoing`.
+pub struct GameActor {
+    setup: GameSetup,
+    game_start: Instant,
+    protocol: Option<SocketProtocol>,
+    encoding: SocketEncoding,
+    // `None` once the attacker's socket is gone
+    outgoing: Option<UnboundedSender<EncodedMessage>>,
+    is_settled: bool,
+    pool: Data<PgPool>,
+    redis_pool: Data<RedisPool>,
+    registry: Addr<GameRegistry>,
+    // Blocking Redis and Postgres work, run off the arbiter by `run_store_jobs`
+    store: UnboundedSender<StoreJob>,
+}
+
+type StoreJob = Box<dyn FnOnce() + Send>;
+
+/// Runs the blocking work of a game on the blocking pool, one job at a time in the order it was
+/// queued, so that spectator events, snapshots and settlement land in order. Jobs still queued
+/// when the actor stops are run all the same.
+async fn run_store_jobs(mut jobs: UnboundedReceiver<StoreJob>) {
+    while let Some(job) = jobs.next().await {
+        let _ = web::block(job).await;
+    }
+}
+
+/// What came of saving a snapshot of the game.
+enum Snapshot {
+    Saved,
+    // The attacker resumed the game on another socket, whose snapshots are left alone
+    Superseded,
+}
+
+/// A frame received on the attacker's socket.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientFrame(pub WsMessage);
+
+/// How a game ended from outside the socket is settled.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GameOutcome {
+    // Settle with the damage and artifacts reached so far
+    #[default]
+    Settle,
+    // Record the game but skip trophy and artifact settlement
+    Void,
+}
+
+/// Ends the game right away and settles it with the state it has reached.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EndGame {
+    pub reason: String,
+    pub outcome: GameOutcome,
+}
+
+/// Warns the attacker that the server shuts down in `drain_time`; the game goes on meanwhile.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DrainGame {
+    pub drain_time: Duration,
+}
+
+/// Saves the game for the attacker to resume on another server and stops without settling it.
+/// A game that cannot be saved is settled instead.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CheckpointGame;
+
+#[derive(Message)]
+#[rtype(result = "GameSummary")]
+pub struct GetGameSummary;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GameSummary {
+    pub game_id: i32,
+    pub attacker_id: i32,
+    pub defender_id: i32,
+    pub frame_no: i32,
+    pub damage_percentage: f32,
+    pub attackers_deployed: i32,
+    pub started_at: i64,
+    pub is_connected: bool,
+}
+
+impl GameActor {
+    pub fn new(
+        setup: GameSetup,
+        outgoing: UnboundedSender<EncodedMessage>,
+        pool: Data<PgPool>,
+        redis_pool: Data<RedisPool>,
+        registry: Addr<GameRegistry>,
+    ) -> GameActor {
+        // The clock keeps running while the attacker is disconnected
+        let time_played = Duration::from_millis(
+            (chrono::Utc::now().timestamp_millis() - setup.started_at).max(0) as u64,
+        );
+        let game_start = Instant::now()
+            .checked_sub(time_played)
+            .unwrap_or_else(Instant::now);
+        let (store, store_jobs) = mpsc::unbounded();
+        actix_rt::spawn(run_store_jobs(store_jobs));
+        GameActor {
+            setup,
+            game_start,
+            protocol: None,
+            encoding: SocketEncoding::Json,
+            outgoing: Some(outgoing),
+            is_settled: false,
+            pool,
+            redis_pool,
+            registry,
+            store,
+        }
+    }
+
+    fn game_duration() -> Duration {
+        Duration::from_secs((GAME_AGE_IN_MINUTES as u64) * 60)
+    }
+
+    fn send<T: Serialize>(&self, encoding: SocketEncoding, message: &T) {
+        let Some(outgoing) = &self.outgoing else {
+            return;
+        };
+        match encoding.encode(message) {
+            Ok(encoded) => {
+                let _ = outgoing.unbounded_send(encoded);
+            }
+            Err(err) => {
+                log::info!(
+                    "Error encoding message for game:{}: {:?}",
+                    self.setup.game_id,
+                    err
+                );
+                self.send_error(SocketErrorCode::InternalError, "Error serializing JSON");
+            }
+        }
+    }
+
+    /// Typed errors for v2 clients; v1 clients only ever got these as plain text.
+    fn send_error(&self, error_code: SocketErrorCode, message: &str) {
+        let has_typed_errors = self
+            .protocol
+            .as_ref()
+            .is_some_and(|protocol| protocol.has_typed_errors());
+        if has_typed_errors {
+            self.send(
+                self.encoding,
+                &send_error_message(self.setup.state.frame_no, error_code, message.to_string()),
+            );
+        } else if let Some(outgoing) = &self.outgoing {
+            let _ = outgoing.unbounded_send(EncodedMessage::Text(message.to_string()));
+        }
+    }
+
+    /// Queues blocking work for the game.
+    fn run(&self, job: impl FnOnce() + Send + 'static) {
+        let _ = self.store.unbounded_send(Box::new(job));
+    }
+
+    /// Queues blocking work for the game, whose result is sent back on the returned receiver.
+    fn queue<R: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> R + Send + 'static,
+    ) -> oneshot::Receiver<R> {
+        let (sender, receiver) = oneshot::channel();
+        self.run(move || {
+            let _ = sender.send(job());
+        });
+        receiver
+    }
+
+    fn publish(&self, response: &SocketResponse) {
+        let seq = self.setup.game_log.e.len();
+        let game_id = self.setup.game_id;
+        let state = self.setup.state.attacker_state_response();
+        let response = response.clone();
+        let redis_pool = self.redis_pool.clone();
+        self.run(move || {
+            let published =
+                redis_pool
+                    .get()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut redis_conn| {
+                        util::publish_game_event(seq, game_id, state, &response, &mut redis_conn)
+                    });
+            if published.is_err() {
+                log::info!("Failed to publish event of game:{}", game_id);
+            }
+        });
+    }
+
+    fn check_superseded(&self) -> oneshot::Receiver<bool> {
+        let game_id = self.setup.game_id;
+        let game_session = self.setup.game_session;
+        let redis_pool = self.redis_pool.clone();
+        self.queue(move || {
+            let Ok(mut redis_conn) = redis_pool.get() else {
+                return false;
+            };
+            matches!(
+                util::get_game_session(game_id, &mut redis_conn),
+                Ok(Some(current_session)) if current_session != game_session
+            )
+        })
+    }
+
+    fn save_snapshot(&self) -> oneshot::Receiver<anyhow::Result<Snapshot>> {
+        let game_id = self.setup.game_id;
+        let game_session = self.setup.game_session;
+        let started_at = self.setup.started_at;
+        let state = self.setup.state.clone();
+        let game_log = self.setup.game_log.clone();
+        let damaged_buildings = self.setup.damaged_buildings.clone();
+        let redis_pool = self.redis_pool.clone();
+        self.queue(move || {
+            let mut redis_conn = redis_pool.get()?;
+            if util::get_game_session(game_id, &mut redis_conn)?
+                .is_some_and(|current_session| current_session != game_session)
+            {
+                return Ok(Snapshot::Superseded);
+            }
+            util::save_game_snapshot(
+                game_session,
+                started_at,
+                &state,
+                &game_log,
+                &damaged_buildings,
+                &mut redis_conn,
+            )?;
+            Ok(Snapshot::Saved)
+        })
+    }
+
+    /// Settles the game once and stops the actor when done, closing the socket if it is still
+    /// open. `game_over` is sent to the attacker last, with the result of the game.
+    fn settle(&mut self, game_over: Option<SocketResponse>, ctx: &mut Context<Self>) {
+        // Nothing else is sent to the attacker while the game is settled
+        let outgoing = self.outgoing.take();
+        if self.is_settled {
+            return;
+        }
+        self.is_settled = true;
+
+        let mut game_log = self.setup.game_log.clone();
+        let state = self.setup.state.clone();
+        let damaged_buildings = self.setup.damaged_buildings.clone();
+        let pool = self.pool.clone();
+        let redis_pool = self.redis_pool.clone();
+        let terminated = self.queue(move || {
+            let mut conn = pool.get()?;
+            let mut redis_conn = redis_pool.get()?;
+            util::terminate_game(
+                &mut game_log,
+                &state,
+                &mut conn,
+                &damaged_buildings,
+                &mut redis_conn,
+            )
+        });
+        ctx.spawn(
+            terminated
+                .into_actor(self)
+                .map(move |terminated, actor, ctx| {
+                    let result = match terminated {
+                        Ok(Ok(result)) => result,
+                        _ => {
+                            log::info!(
+                                "Error terminating the game for game:{} and attacker:{} and opponent:{}",
+                                actor.setup.game_id,
+                                actor.setup.attacker_id,
+                                actor.setup.defender_id
+                            );
+                            None
+                        }
+                    };
+                    if let Some(mut response) = game_over {
+                        response.result = result;
+                        actor.outgoing = outgoing;
+                        actor.send(actor.encoding, &response);
+                        actor.outgoing = None;
+                    }
+                    ctx.stop();
+                }),
+        );
+    }
+
+    fn end_game(&mut self, message: &str, ctx: &mut Context<Self>) {
+        if self.is_settled {
+            return;
+        }
+        let response = send_terminate_game_message(self.setup.state.frame_no, message.to_string());
+        self.publish(&response);
+        self.settle(Some(response), ctx);
+    }
+
+    /// Saves the game and stops, or settles it if it cannot be saved. `done` is sent once
+    /// either is over.
+    fn checkpoint(&mut self, ctx: &mut Context<Self>, done: oneshot::Sender<()>) {
+        let saved = self.save_snapshot();
+        ctx.spawn(saved.into_actor(self).map(|saved, actor, ctx| {
+            match saved {
+                Ok(Ok(Snapshot::Saved)) => {
+                    log::info!(
+                        "Game:{} is checkpointed for Attacker:{} and Defender:{}",
+                        actor.setup.game_id,
+                        actor.setup.attacker_id,
+                        actor.setup.defender_id
+                    );
+                    let remaining_time = Self::game_duration()
+                        .saturating_sub(actor.game_start.elapsed())
+                        .as_millis() as u64;
+                    actor.send(
+                        actor.encoding,
+                        &send_shutdown_message(
+                            actor.setup.state.frame_no,
+                            remaining_time,
+                            String::from("Game saved, reconnect to resume"),
+                        ),
+                    );
+                    actor.outgoing = None;
+                    ctx.stop();
+                }
+                Ok(Ok(Snapshot::Superseded)) => {
+                    actor.outgoing = None;
+                    ctx.stop();
+                }
+                _ => {
+                    log::info!(
+                        "Failed to checkpoint game:{}, settling it",
+                        actor.setup.game_id
+                    );
+                    actor.end_game("Server shutting down", ctx);
+                }
+            }
+            // Runs after the settlement, if any, as jobs are run in order
+            actor.run(move || {
+                let _ = done.send(());
+            });
+        }));
+    }
+
+    fn tick(&mut self, ctx: &mut Context<Self>) {
+        // A suspended game waits for its reconnect window instead
+        if self.outgoing.is_none() {
+            return;
+        }
+
+        let saved = self.save_snapshot();
+        ctx.spawn(saved.into_actor(self).map(|saved, actor, ctx| match saved {
+            Ok(Ok(Snapshot::Saved)) => {}
+            Ok(Ok(Snapshot::Superseded)) => {
+                if actor.outgoing.is_some() && !actor.is_settled {
+                    log::info!(
+                        "Game:{} was resumed on another socket by Attacker:{}",
+                        actor.setup.game_id,
+                        actor.setup.attacker_id
+                    );
+                    actor.outgoing = None;
+                    ctx.stop();
+                }
+            }
+            _ => log::info!("Failed to save snapshot of game:{}", actor.setup.game_id),
+        }));
+
+        let elapsed = self.game_start.elapsed();
+        if elapsed >= Self::game_duration() {
+            log::info!(
+                "Game:{} is timed out for Attacker:{} and Defender:{}",
+                self.setup.game_id,
+                self.setup.attacker_id,
+                self.setup.defender_id
+            );
+            self.end_game("Time up", ctx);
+            return;
+        }
+
+        // Clients are served v1 until they send a handshake
+        let is_clock_synced = match &self.protocol {
+            Some(protocol) => protocol.supports(CLOCK_SYNC_CAPABILITY),
+            None => true,
+        };
+        if is_clock_synced {
+            let remaining_time = (Self::game_duration() - elapsed).as_millis() as u64;
+            let response = send_clock_sync_message(self.setup.state.frame_no, remaining_time);
+            self.publish(&response);
+            self.send(self.encoding, &response);
+        }
+    }
+
+    /// Keeps the game for `RECONNECT_GRACE_IN_SECONDS` after the socket drops, in case the
+    /// attacker comes back on a new one.
+    fn suspend(&mut self, ctx: &mut Context<Self>) {
+        self.outgoing = None;
+        let saved = self.save_snapshot();
+        ctx.spawn(saved.into_actor(self).map(|saved, actor, ctx| match saved {
+            Ok(Ok(Snapshot::Saved)) => {
+                log::info!(
+                    "Game:{} is suspended for Attacker:{} and Defender:{}",
+                    actor.setup.game_id,
+                    actor.setup.attacker_id,
+                    actor.setup.defender_id
+                );
+                ctx.run_later(
+                    Duration::from_secs(RECONNECT_GRACE_IN_SECONDS),
+                    |actor, ctx| {
+                        let is_superseded = actor.check_superseded();
+                        ctx.spawn(is_superseded.into_actor(actor).map(
+                            |is_superseded, actor, ctx| {
+                                if is_superseded == Ok(true) {
+                                    ctx.stop();
+                                    return;
+                                }
+                                actor.end_game("Attacker disconnected", ctx);
+                            },
+                        ));
+                    },
+                );
+            }
+            Ok(Ok(Snapshot::Superseded)) => ctx.stop(),
+            _ => actor.settle(None, ctx),
+        }));
+    }
+
+    /// The first text frame may be a handshake. It is answered in JSON whatever encoding is
+    /// agreed on, since the client cannot know it yet.
+    fn handle_handshake(&mut self, text: &str) -> bool {
+        let Ok(handshake) = serde_json::from_str::<HandshakeRequest>(text) else {
+            return false;
+        };
+        match SocketProtocol::negotiate(&handshake) {
+            Some(negotiated) => {
+                log::info!(
+                    "Game:{} is using socket protocol v{}",
+                    self.setup.game_id,
+                    negotiated.version
+                );
+                self.send(SocketEncoding::Json, &negotiated.handshake_response());
+                self.encoding = negotiated.encoding();
+                self.protocol = Some(negotiated);
+            }
+            None => self.send(
+                SocketEncoding::Json,
+                &send_error_message(
+                    self.setup.state.frame_no,
+                    SocketErrorCode::UnsupportedProtocolVersion,
+                    format!("Protocol v{} is not supported", handshake.protocol_version),
+                ),
+            ),
+        }
+        true
+    }
+
+    fn handle_client_message(&mut self, msg: WsMessage, ctx: &mut Context<Self>) {
+        if self.protocol.is_none() {
+            if let WsMessage::Text(text) = &msg {
+                if self.handle_handshake(text) {
+                    return;
+                }
+            }
+            self.protocol = Some(SocketProtocol::legacy());
+        }
+
+        let client_message = match &msg {
+            WsMessage::Text(text) => serde_json::from_str::<ClientMessage>(text).ok(),
+            WsMessage::Binary(bytes) if self.encoding == SocketEncoding::MessagePack => {
+                self.encoding.decode::<ClientMessage>(bytes).ok()
+            }
+            _ => None,
+        };
+        let Some(client_message) = client_message else {
+            log::info!(
+                "Error parsing JSON for game:{} and attacker:{} and opponent:{}",
+                self.setup.game_id,
+                self.setup.attacker_id,
+                self.setup.defender_id
+            );
+            self.send_error(SocketErrorCode::MalformedMessage, "Error parsing JSON");
+            return;
+        };
+
+        let frame_number = client_message.frame_number();
+        let clock_frame =
+            (self.game_start.elapsed().as_millis() / FRAME_DURATION_IN_MILLIS as u128) as i32;
+        let setup = &mut self.setup;
+        let response_result = if frame_number > clock_frame + MAX_FRAMES_AHEAD_OF_CLOCK {
+            setup
+                .state
+                .invalidate(frame_number, "Frame ahead of server clock");
+            if setup.state.in_validation.is_invalidated {
+                Some(Ok(send_terminate_game_message(
+                    frame_number,
+                    setup.state.in_validation.message.clone(),
+                )))
+            } else {
+                Some(Err(anyhow::anyhow!("Frame ahead of server clock, ignored")))
+            }
+        } else {
+            match client_message {
+                ClientMessage::Single(socket_request) => game_handler(
+                    &setup.attacker_types,
+                    socket_request,
+                    &mut setup.state,
+                    &setup.shortest_paths,
+                    &setup.roads,
+                    &setup.bomb_types,
+                    &mut setup.game_log,
+                ),
+                ClientMessage::Batch(batch_request) => batch_handler(
+                    &setup.attacker_types,
+                    batch_request,
+                    &mut setup.state,
+                    &setup.shortest_paths,
+                    &setup.roads,
+                    &setup.bomb_types,
+                    &mut setup.game_log,
+                ),
+            }
+        };
+
+        match response_result {
+            Some(Ok(response)) => self.handle_response(response, ctx),
+            Some(Err(err)) => {
+                log::info!(
+                    "Error: {:?} while handling for game:{} and attacker:{} and opponent:{}",
+                    err,
+                    self.setup.game_id,
+                    self.setup.attacker_id,
+                    self.setup.defender_id
+                );
+                // v1 clients were never told about rejected actions
+                if self
+                    .protocol
+                    .as_ref()
+                    .is_some_and(|protocol| protocol.has_typed_errors())
+                {
+                    self.send_error(SocketErrorCode::InvalidAction, &err.to_string());
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn handle_response(&mut self, response: SocketResponse, ctx: &mut Context<Self>) {
+        self.publish(&response);
+
+        // Buildings damaged so far are what the attacker is credited for on settlement
+        let has_damaged_buildings = matches!(
+            response.result_type,
+            ResultType::BuildingsDamaged | ResultType::Batch | ResultType::GameOver
+        );
+        if has_damaged_buildings {
+            if let Some(buildings) = &response.damaged_buildings {
+                self.setup
+                    .damaged_buildings
+                    .extend(buildings.iter().cloned());
+            }
+        }
+
+        if response.result_type == ResultType::GameOver {
+            self.settle(Some(response), ctx);
+        } else {
+            self.send(self.encoding, &response);
+        }
+    }
+
+    pub fn summary(&self) -> GameSummary {
+        GameSummary {
+            game_id: self.setup.game_id,
+            attacker_id: self.setup.attacker_id,
+            defender_id: self.setup.defender_id,
+            frame_no: self.setup.state.frame_no,
+            damage_percentage: self.setup.state.damage_percentage,
+            attackers_deployed: self.setup.state.attackers_deployed,
+            started_at: self.setup.started_at,
+            is_connected: self.outgoing.is_some(),
+        }
+    }
+}
+
+impl Actor for GameActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.registry.do_send(RegisterGame {
+            game_id: self.setup.game_id,
+            game: ctx.address(),
+        });
+
+        log::info!(
+            "Game:{} is ready to be played for Attacker:{} and Defender:{}",
+            self.setup.game_id,
+            self.setup.attacker_id,
+            self.setup.defender_id
+        );
+
+        if self.setup.is_resumed {
+            let remaining_time = Self::game_duration()
+                .saturating_sub(self.game_start.elapsed())
+                .as_millis() as u64;
+            self.send(
+                self.encoding,
+                &send_resume_message(self.setup.state.frame_no, remaining_time),
+            );
+        }
+
+        ctx.run_interval(
+            Duration::from_secs(CLOCK_SYNC_INTERVAL_IN_SECONDS),
+            |actor, ctx| actor.tick(ctx),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Context<Self>) {
+        self.registry.do_send(UnregisterGame {
+            game_id: self.setup.game_id,
+            game: ctx.address(),
+        });
+        log::info!(
+            "End of Game:{}, Attacker:{} and Defender:{}",
+            self.setup.game_id,
+            self.setup.attacker_id,
+            self.setup.defender_id
+        );
+    }
+}
+
+impl Handler<ClientFrame> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientFrame, ctx: &mut Context<Self>) {
+        match msg.0 {
+            msg @ (WsMessage::Text(_) | WsMessage::Binary(_)) => {
+                // Frames still queued from a socket that already went away
+                if self.outgoing.is_some() {
+                    self.handle_client_message(msg, ctx);
+                }
+            }
+            WsMessage::Close(_) => {
+                if self.outgoing.is_some() {
+                    self.suspend(ctx);
+                }
+            }
+            _ => {
+                log::info!(
+                    "Unknown message type for game:{} and attacker:{} and opponent:{}",
+                    self.setup.game_id,
+                    self.setup.attacker_id,
+                    self.setup.defender_id
+                );
+            }
+        }
+    }
+}
+
+impl Handler<EndGame> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: EndGame, ctx: &mut Context<Self>) {
+        log::info!("Game:{} is being ended: {}", self.setup.game_id, msg.reason);
+        if msg.outcome == GameOutcome::Void {
+            self.setup
+                .state
+                .void(self.setup.state.frame_no, &msg.reason);
+        }
+        self.end_game(&msg.reason, ctx);
+    }
+}
+
+impl Handler<DrainGame> for GameActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: DrainGame, _ctx: &mut Context<Self>) {
+        self.send(
+            self.encoding,
+            &send_shutdown_message(
+                self.setup.state.frame_no,
+                msg.drain_time.as_millis() as u64,
+                String::from("Server is shutting down"),
+            ),
+        );
+    }
+}
+
+impl Handler<CheckpointGame> for GameActor {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _msg: CheckpointGame, ctx: &mut Context<Self>) -> Self::Result {
+        let (done, checkpointed) = oneshot::channel();
+        self.checkpoint(ctx, done);
+        Box::pin(async move {
+            let _ = checkpointed.await;
+        })
+    }
+}
+
+impl Handler<GetGameSummary> for GameActor {
+    type Result = MessageResult<GetGameSummary>;
+
+    fn handle(&mut self, _msg: GetGameSummary, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.summary())
+    }
+}
@@ -1,3 +1,6 @@
+use self::game_actor::{ClientFrame, GameActor, GameSetup};
+use self::registry::GameRegistry;
+use self::shutdown::ShutdownFlag;
 use self::util::{get_valid_road_paths, AttackResponse, GameLog, ResultResponse};
 use super::auth::session::AuthUser;
 use super::defense::shortest_path::run_shortest_paths;
@@ -6,26 +9,32 @@ use super::defense::util::{
 };
 use super::user::util::fetch_user;
 use super::{error, PgPool, RedisPool};
-use crate::api::attack::socket::{BuildingResponse, ResultType, SocketRequest, SocketResponse};
-use crate::api::util::HistoryboardQuery;
-use crate::constants::{GAME_AGE_IN_MINUTES, MAX_BOMBS_PER_ATTACK};
-use crate::models::{AttackerType, User};
+use crate::api::attack::socket::EncodedMessage;
+use crate::api::util::{get_current_levels_fixture, HistoryboardQuery};
+use crate::constants::{MAX_BOMBS_PER_ATTACK, REPLAY_LOG_VERSION};
+use crate::models::{AttackerType, LevelsFixture, User};
 use crate::validator::state::State;
-use crate::validator::util::{BombType, BuildingDetails, DefenderDetails, MineDetails};
+use crate::validator::util::{
+    BombType, BuildingDetails, DefenderDetails, MineDetails, ValidationPolicy,
+};
 use crate::validator::util::{Coords, SourceDestXY};
 use actix_rt;
-use actix_web::error::ErrorBadRequest;
+use actix_web::error::{ErrorBadRequest, ErrorServiceUnavailable};
 use actix_web::web::{Data, Json};
 use actix_web::{web, Error, HttpRequest, HttpResponse, Responder, Result};
 use log;
 use std::collections::{HashMap, HashSet};
-use std::time;
 
-use crate::validator::game_handler;
+use actix::{Actor, Addr};
 use actix_ws::Message;
+use futures::channel::mpsc;
 use futures_util::stream::StreamExt;
 
+pub mod game_actor;
 mod rating;
+pub mod reaper;
+pub mod registry;
+pub mod shutdown;
 pub mod socket;
 pub mod util;
 
@@ -39,9 +48,13 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
 async fn init_attack(
     pool: web::Data<PgPool>,
     redis_pool: Data<RedisPool>,
+    shutdown: Data<ShutdownFlag>,
     user: AuthUser,
 ) -> Result<impl Responder> {
     let attacker_id = user.0;
+    if shutdown.is_shutting_down() {
+        return Err(ErrorServiceUnavailable("Server is shutting down"));
+    }
 
     log::info!("Attacker:{} is trying to initiate an attack", attacker_id);
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
@@ -108,11 +121,13 @@ async fn init_attack(
 
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
 
-    let obtainable_artifacts = web::block(move || {
-        Ok(util::artifacts_obtainable_from_base(map_id, &mut conn)?) as anyhow::Result<i32>
-    })
-    .await?
-    .map_err(|err| error::handle_error(err.into()))?;
+    let lootable_buildings = web::block(move || util::get_lootable_buildings(map_id, &mut conn))
+        .await?
+        .map_err(|err| error::handle_error(err.into()))?;
+    let obtainable_artifacts = lootable_buildings
+        .iter()
+        .map(|building| building.lootable_artifacts)
+        .sum();
 
     log::info!(
         "Artifacts obtainable from opponent: {} base is {}",
@@ -169,6 +184,7 @@ async fn init_attack(
         },
         shortest_paths: None,
         obtainable_artifacts,
+        lootable_buildings,
         attack_token,
         attacker_types: opponent_base.attacker_types,
         bomb_types: opponent_base.bomb_types,
@@ -186,9 +202,16 @@ async fn init_attack(
 async fn socket_handler(
     pool: web::Data<PgPool>,
     redis_pool: Data<RedisPool>,
+    registry: Data<Addr<GameRegistry>>,
+    shutdown: Data<ShutdownFlag>,
     req: HttpRequest,
     body: web::Payload,
 ) -> Result<HttpResponse, Error> {
+    // Checkpointed games are resumed on the next server
+    if shutdown.is_shutting_down() {
+        return Err(ErrorServiceUnavailable("Server is shutting down"));
+    }
+
     let query_params = req.query_string().split('&').collect::<Vec<&str>>();
     let user_token = query_params[0].split('=').collect::<Vec<&str>>()[1];
     let attack_token = query_params[1].split('=').collect::<Vec<&str>>()[1];
@@ -226,14 +249,27 @@ async fn socket_handler(
         .get()
         .map_err(|err| error::handle_error(err.into()))?;
 
-    if let Ok(Some(_)) = util::get_game_id_from_redis(attacker_id, &mut redis_conn, true) {
-        log::info!("Attacker:{} has an ongoing game", attacker_id);
-        return Err(ErrorBadRequest("Attacker has an ongoing game"));
-    }
+    // A dropped game leaves a snapshot behind and can be resumed with the same attack token
+    let snapshot = match util::get_game_snapshot(game_id, &mut redis_conn) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            log::info!("Failed to load snapshot of game:{}: {:?}", game_id, err);
+            None
+        }
+    };
+
+    if snapshot.is_some() {
+        log::info!("Attacker:{} is resuming game:{}", attacker_id, game_id);
+    } else {
+        if let Ok(Some(_)) = util::get_game_id_from_redis(attacker_id, &mut redis_conn, true) {
+            log::info!("Attacker:{} has an ongoing game", attacker_id);
+            return Err(ErrorBadRequest("Attacker has an ongoing game"));
+        }
 
-    if let Ok(Some(_)) = util::get_game_id_from_redis(defender_id, &mut redis_conn, false) {
-        log::info!("Defender:{} has an ongoing game", defender_id);
-        return Err(ErrorBadRequest("Defender has an ongoing game"));
+        if let Ok(Some(_)) = util::get_game_id_from_redis(defender_id, &mut redis_conn, false) {
+            log::info!("Defender:{} has an ongoing game", defender_id);
+            return Err(ErrorBadRequest("Defender has an ongoing game"));
+        }
     }
 
     if util::check_and_remove_incomplete_game(&attacker_id, &defender_id, &game_id, &mut conn)
@@ -315,10 +351,11 @@ async fn socket_handler(
     .map_err(|err| error::handle_error(err.into()))?;
 
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
-    let bomb_types =
-        web::block(move || Ok(util::get_bomb_types(&mut conn)?) as anyhow::Result<Vec<BombType>>)
-            .await?
-            .map_err(|err| error::handle_error(err.into()))?;
+    let bomb_types = web::block(move || {
+        Ok(util::get_bomb_types(&mut conn, attacker_id)?) as anyhow::Result<Vec<BombType>>
+    })
+    .await?
+    .map_err(|err| error::handle_error(err.into()))?;
 
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
     let attacker_type = web::block(move || {
@@ -327,6 +364,13 @@ async fn socket_handler(
     .await?
     .map_err(|err| error::handle_error(err.into()))?;
 
+    let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
+    let levels_fixture = web::block(move || {
+        Ok(get_current_levels_fixture(&mut conn)?) as anyhow::Result<LevelsFixture>
+    })
+    .await?
+    .map_err(|err| error::handle_error(err.into()))?;
+
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
 
     let attacker_user_details =
@@ -356,18 +400,22 @@ async fn socket_handler(
         return Err(ErrorBadRequest("User details not found"));
     }
 
-    let redis_conn = redis_pool
+    let mut redis_conn = redis_pool
         .get()
         .map_err(|err| error::handle_error(err.into()))?;
 
-    if util::add_game_id_to_redis(attacker_id, defender_id, game_id, redis_conn).is_err() {
+    let game_session = util::start_game_session(game_id, &mut redis_conn)
+        .map_err(|err| error::handle_error(err.into()))?;
+
+    if snapshot.is_none()
+        && util::add_game_id_to_redis(attacker_id, defender_id, game_id, redis_conn).is_err()
+    {
         println!("Cannot add game:{} to redis", game_id);
         return Err(ErrorBadRequest("Internal Server Error"));
     }
 
-    let mut damaged_buildings: Vec<BuildingResponse> = Vec::new();
-
     let game_log = GameLog {
+        v: REPLAY_LOG_VERSION,
         g: game_id,
         a: attacker_user_details.unwrap(),
         d: defender_user_details.unwrap(),
@@ -382,6 +430,9 @@ async fn socket_handler(
             nd: 0,
             oa: 0,
             od: 0,
+            ls: Vec::new(),
+            lu: 0,
+            lx: 0,
         },
     };
 
@@ -392,6 +443,35 @@ async fn socket_handler(
         defender_id
     );
 
+    let is_resumed = snapshot.is_some();
+    let (state, game_log, damaged_buildings, started_at) = match snapshot {
+        Some(snapshot) => (
+            snapshot.state,
+            snapshot.game_log,
+            snapshot.damaged_buildings,
+            snapshot.started_at,
+        ),
+        None => {
+            let mut game_state = State::new(
+                attacker_id,
+                defender_id,
+                defenders,
+                hut_defenders,
+                mines,
+                buildings,
+                levels_fixture.no_of_attackers,
+            );
+            game_state.set_total_hp_buildings();
+            game_state.set_validation_policy(ValidationPolicy::from_env());
+            (
+                game_state,
+                game_log,
+                Vec::new(),
+                chrono::Utc::now().timestamp_millis(),
+            )
+        }
+    };
+
     let (response, session, mut msg_stream) = actix_ws::handle(&req, body)?;
 
     log::info!(
@@ -401,237 +481,80 @@ async fn socket_handler(
         defender_id
     );
 
-    let mut session_clone1 = session.clone();
-    let mut session_clone2 = session.clone();
-
-    actix_rt::spawn(async move {
-        let mut game_state = State::new(
-            attacker_id,
-            defender_id,
-            defenders,
-            hut_defenders,
-            mines,
-            buildings,
-        );
-        game_state.set_total_hp_buildings();
-
-        let game_logs = &mut game_log.clone();
-
-        let mut conn = pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))
-            .unwrap();
-
-        let mut redis_conn = redis_pool
-            .clone()
-            .get()
-            .map_err(|err| error::handle_error(err.into()))
-            .unwrap();
-
-        let shortest_path = &shortest_paths.clone();
-        let roads = &roads.clone();
-        let bomb_types = &bomb_types.clone();
-        let attacker_type = &attacker_type.clone();
-
-        log::info!(
-            "Game:{} is ready to be played for Attacker:{} and Defender:{}",
+    let (outgoing, mut outgoing_messages) = mpsc::unbounded();
+    let game = GameActor::new(
+        GameSetup {
             game_id,
             attacker_id,
-            defender_id
-        );
-
-        while let Some(Ok(msg)) = msg_stream.next().await {
-            match msg {
-                Message::Ping(bytes) => {
-                    if session_clone1.pong(&bytes).await.is_err() {
-                        return;
-                    }
-                }
-                Message::Text(s) => {
-                    if let Ok(socket_request) = serde_json::from_str::<SocketRequest>(&s) {
-                        let response_result = game_handler(
-                            attacker_type,
-                            socket_request,
-                            &mut game_state,
-                            shortest_path,
-                            roads,
-                            bomb_types,
-                            game_logs,
-                        );
-                        match response_result {
-                            Some(Ok(response)) => {
-                                if let Ok(response_json) = serde_json::to_string(&response) {
-                                    // println!("Response Json ---- {}", response_json);
-                                    if response.result_type == ResultType::GameOver {
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                        if (session_clone1.clone().close(None).await).is_err() {
-                                            log::info!("Error closing the socket connection for game:{} and attacker:{} and opponent:{}", game_id, attacker_id, defender_id);
-                                        }
-                                        if util::terminate_game(
-                                            game_logs,
-                                            &mut conn,
-                                            &damaged_buildings,
-                                            &mut redis_conn,
-                                        )
-                                        .is_err()
-                                        {
-                                            log::info!("Error terminating the game 1 for game:{} and attacker:{} and opponent:{}", game_id, attacker_id, defender_id);
-                                        }
-                                    } else if response.result_type == ResultType::MinesExploded {
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::DefendersDamaged {
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::DefendersTriggered
-                                    {
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::SpawnHutDefender {
-                                        // game_state.hut.hut_defenders_count -= 1;
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::BuildingsDamaged {
-                                        damaged_buildings
-                                            .extend(response.damaged_buildings.unwrap());
-                                        // if util::deduct_artifacts_from_building(
-                                        //     response.damaged_buildings.unwrap(),
-                                        //     &mut conn,
-                                        // )
-                                        // .is_err()
-                                        // {
-                                        //     log::info!("Failed to deduct artifacts from building for game:{} and attacker:{} and opponent:{}", game_id, attacker_id, defender_id);
-                                        // }
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::PlacedAttacker {
-                                        if session_clone1.text(response_json).await.is_err() {
-                                            return;
-                                        }
-                                    } else if response.result_type == ResultType::Nothing
-                                        && session_clone1.text(response_json).await.is_err()
-                                    {
-                                        return;
-                                    }
-                                } else {
-                                    log::info!("Error serializing JSON for game:{} and attacker:{} and opponent:{}", game_id, attacker_id, defender_id);
-                                    if session_clone1.text("Error serializing JSON").await.is_err()
-                                    {
-                                        return;
-                                    }
-                                }
-                            }
-                            Some(Err(err)) => {
-                                log::info!("Error: {:?} while handling for game:{} and attacker:{} and opponent:{}", err, game_id, attacker_id, defender_id);
-                            }
-                            None => {
-                                // Handle the case where game_handler returned None (e.g., ActionType::PlaceAttacker)
-                                // Add appropriate logic here based on the requirements.
-                                log::info!("All fine for now");
-                            }
-                        }
-                    } else {
-                        log::info!(
-                            "Error parsing JSON for game:{} and attacker:{} and opponent:{}",
-                            game_id,
-                            attacker_id,
-                            defender_id
-                        );
-
-                        if session_clone1.text("Error parsing JSON").await.is_err() {
-                            return;
-                        }
-                    }
-                }
-                Message::Close(_s) => {
-                    if util::terminate_game(
-                        game_logs,
-                        &mut conn,
-                        &damaged_buildings,
-                        &mut redis_conn,
-                    )
-                    .is_err()
-                    {
-                        log::info!("Error terminating the game 2 for game:{} and attacker:{} and opponent:{}", game_id, attacker_id, defender_id);
-                    }
-                    break;
-                }
-                _ => {
-                    log::info!(
-                        "Unknown message type for game:{} and attacker:{} and opponent:{}",
-                        game_id,
-                        attacker_id,
-                        defender_id
-                    );
-                }
+            defender_id,
+            game_session,
+            started_at,
+            is_resumed,
+            state,
+            game_log,
+            damaged_buildings,
+            attacker_types: attacker_type,
+            shortest_paths,
+            roads,
+            bomb_types,
+        },
+        outgoing,
+        pool,
+        redis_pool,
+        registry.get_ref().clone(),
+    )
+    .start();
+
+    // Writes what the game sends, and closes the socket once the game lets go of it
+    let mut write_session = session.clone();
+    actix_rt::spawn(async move {
+        while let Some(message) = outgoing_messages.next().await {
+            if send_encoded(&mut write_session, message).await.is_err() {
+                return;
             }
         }
+        if write_session.close(None).await.is_err() {
+            log::info!(
+                "Error closing the socket connection for game:{} and attacker:{} and opponent:{}",
+                game_id,
+                attacker_id,
+                defender_id
+            );
+        }
     });
 
+    // Hands frames to the game; a socket that drops without a close frame can be resumed all
+    // the same
+    let mut read_session = session;
     actix_rt::spawn(async move {
-        let timeout_duration = time::Duration::from_secs((GAME_AGE_IN_MINUTES as u64) * 60);
-        let last_activity = time::Instant::now();
-
-        log::info!(
-            "Timer started for Game:{}, Attacker:{} and Defender:{}",
-            game_id,
-            attacker_id,
-            defender_id
-        );
-
-        loop {
-            actix_rt::time::sleep(time::Duration::from_secs(1)).await;
-
-            if time::Instant::now() - last_activity > timeout_duration {
-                log::info!(
-                    "Game:{} is timed out for Attacker:{} and Defender:{}",
-                    game_id,
-                    attacker_id,
-                    defender_id
-                );
-
-                let response_json = serde_json::to_string(&SocketResponse {
-                    frame_number: 0,
-                    result_type: ResultType::GameOver,
-                    is_alive: None,
-                    attacker_health: None,
-                    exploded_mines: None,
-                    defender_damaged: None,
-                    hut_triggered: false,
-                    hut_defenders: None,
-                    damaged_buildings: None,
-                    total_damage_percentage: None,
-                    is_sync: false,
-                    is_game_over: true,
-                    message: Some("Connection timed out".to_string()),
-                })
-                .unwrap();
-                if session_clone2.text(response_json).await.is_err() {
-                    return;
+        while let Some(Ok(msg)) = msg_stream.next().await {
+            if let Message::Ping(bytes) = &msg {
+                if read_session.pong(bytes).await.is_err() {
+                    break;
                 }
-
-                break;
+                continue;
+            }
+            let is_close = matches!(msg, Message::Close(_));
+            if game.send(ClientFrame(msg)).await.is_err() || is_close {
+                return;
             }
         }
+        game.do_send(ClientFrame(Message::Close(None)));
     });
 
-    log::info!(
-        "End of Game:{}, Attacker:{} and Defender:{}",
-        game_id,
-        attacker_id,
-        defender_id,
-    );
-
     Ok(response)
 }
 
+async fn send_encoded(
+    session: &mut actix_ws::Session,
+    message: EncodedMessage,
+) -> Result<(), actix_ws::Closed> {
+    match message {
+        EncodedMessage::Text(text) => session.text(text).await,
+        EncodedMessage::Binary(bytes) => session.binary(bytes).await,
+    }
+}
+
 async fn attack_history(
     pool: web::Data<PgPool>,
     user: AuthUser,
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::web::{self, Data};
+
+use super::registry::{GameRegistry, ListGameIds};
+use super::util;
+use crate::api::{PgPool, RedisPool};
+use crate::constants::STALE_GAME_REAPER_INTERVAL_IN_SECONDS;
+
+/// Periodically settles or voids games nobody is left to settle, such as those cut off by a
+/// server crash, so that their players are not stuck with an ongoing game.
+pub struct GameReaper {
+    pool: Data<PgPool>,
+    redis_pool: Data<RedisPool>,
+    registry: Addr<GameRegistry>,
+}
+
+impl GameReaper {
+    pub fn new(
+        pool: Data<PgPool>,
+        redis_pool: Data<RedisPool>,
+        registry: Addr<GameRegistry>,
+    ) -> GameReaper {
+        GameReaper {
+            pool,
+            redis_pool,
+            registry,
+        }
+    }
+
+    fn reap(&mut self, ctx: &mut Context<Self>) {
+        let pool = self.pool.clone();
+        let redis_pool = self.redis_pool.clone();
+        let registry = self.registry.clone();
+
+        // Waits so that a slow run is never overlapped by the next one
+        ctx.wait(
+            async move {
+                let live_games: HashSet<i32> = match registry.send(ListGameIds).await {
+                    Ok(game_ids) => game_ids.into_iter().collect(),
+                    Err(_) => return,
+                };
+                let reaped = web::block(move || {
+                    let mut conn = pool.get()?;
+                    let mut redis_conn = redis_pool.get()?;
+                    util::reap_stale_games(&live_games, &mut conn, &mut redis_conn)
+                })
+                .await;
+                match reaped {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => log::info!("Error reaping stale games: {:?}", err),
+                    Err(err) => log::info!("Error reaping stale games: {:?}", err),
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Actor for GameReaper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        log::info!("Stale game reaper started");
+        self.reap(ctx);
+        ctx.run_interval(
+            Duration::from_secs(STALE_GAME_REAPER_INTERVAL_IN_SECONDS),
+            |actor, ctx| actor.reap(ctx),
+        );
+    }
+}
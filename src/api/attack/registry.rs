@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix::prelude::*;
+use futures::future::join_all;
+
+use super::game_actor::{
+    CheckpointGame, DrainGame, EndGame, GameActor, GameOutcome, GameSummary, GetGameSummary,
+};
+
+/// Every game actor running in this process, by game id.
+///
+/// Games register themselves when they start and leave when they stop, so the registry is the
+/// one place to find, message or end a live game.
+#[derive(Default)]
+pub struct GameRegistry {
+    games: HashMap<i32, Addr<GameActor>>,
+}
+
+impl Actor for GameRegistry {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterGame {
+    pub game_id: i32,
+    pub game: Addr<GameActor>,
+}
+
+/// Only removes `game` itself, not an actor that has since taken over the game id after a
+/// reconnect.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnregisterGame {
+    pub game_id: i32,
+    pub game: Addr<GameActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Addr<GameActor>>")]
+pub struct LookupGame {
+    pub game_id: i32,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<GameSummary>")]
+pub struct ListGames;
+
+#[derive(Message)]
+#[rtype(result = "Vec<i32>")]
+pub struct ListGameIds;
+
+/// Ends a game by id once it has been settled; answers whether it was running here.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct EndGameById {
+    pub game_id: i32,
+    pub reason: String,
+    pub outcome: GameOutcome,
+}
+
+/// Warns every game that the server shuts down in `drain_time`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DrainGames {
+    pub drain_time: Duration,
+}
+
+/// Checkpoints every game; answers once they have all been saved or settled.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CheckpointGames;
+
+impl Handler<RegisterGame> for GameRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterGame, _ctx: &mut Context<Self>) {
+        self.games.insert(msg.game_id, msg.game);
+    }
+}
+
+impl Handler<UnregisterGame> for GameRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnregisterGame, _ctx: &mut Context<Self>) {
+        if self.games.get(&msg.game_id) == Some(&msg.game) {
+            self.games.remove(&msg.game_id);
+        }
+    }
+}
+
+impl Handler<LookupGame> for GameRegistry {
+    type Result = Option<Addr<GameActor>>;
+
+    fn handle(&mut self, msg: LookupGame, _ctx: &mut Context<Self>) -> Self::Result {
+        self.games.get(&msg.game_id).cloned()
+    }
+}
+
+impl Handler<ListGames> for GameRegistry {
+    type Result = ResponseFuture<Vec<GameSummary>>;
+
+    fn handle(&mut self, _msg: ListGames, _ctx: &mut Context<Self>) -> Self::Result {
+        let summaries = join_all(self.games.values().map(|game| game.send(GetGameSummary)));
+        Box::pin(async move {
+            // A game that stops while being asked is left out
+            summaries
+                .await
+                .into_iter()
+                .filter_map(|summary| summary.ok())
+                .collect()
+        })
+    }
+}
+
+impl Handler<ListGameIds> for GameRegistry {
+    type Result = Vec<i32>;
+
+    fn handle(&mut self, _msg: ListGameIds, _ctx: &mut Context<Self>) -> Self::Result {
+        self.games.keys().copied().collect()
+    }
+}
+
+impl Handler<EndGameById> for GameRegistry {
+    type Result = ResponseFuture<bool>;
+
+    fn handle(&mut self, msg: EndGameById, _ctx: &mut Context<Self>) -> Self::Result {
+        let Some(game) = self.games.get(&msg.game_id).cloned() else {
+            return Box::pin(async { false });
+        };
+        Box::pin(async move {
+            // A game that stops on its own meanwhile has been settled all the same
+            let _ = game
+                .send(EndGame {
+                    reason: msg.reason,
+                    outcome: msg.outcome,
+                })
+                .await;
+            true
+        })
+    }
+}
+
+impl Handler<DrainGames> for GameRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: DrainGames, _ctx: &mut Context<Self>) {
+        for game in self.games.values() {
+            game.do_send(DrainGame {
+                drain_time: msg.drain_time,
+            });
+        }
+    }
+}
+
+impl Handler<CheckpointGames> for GameRegistry {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _msg: CheckpointGames, _ctx: &mut Context<Self>) -> Self::Result {
+        let checkpoints = join_all(self.games.values().map(|game| game.send(CheckpointGame)));
+        Box::pin(async move {
+            checkpoints.await;
+        })
+    }
+}
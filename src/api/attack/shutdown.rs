@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+use actix_web::web::Data;
+use futures_util::future::select;
+
+use super::registry::{CheckpointGames, DrainGames, GameRegistry, ListGameIds};
+use crate::constants::SHUTDOWN_DRAIN_IN_SECONDS;
+
+/// Set once the server starts shutting down, after which no new attacks are started.
+#[derive(Default)]
+pub struct ShutdownFlag(AtomicBool);
+
+impl ShutdownFlag {
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Waits for SIGTERM or Ctrl-C, then shuts the server down without dropping live attacks.
+///
+/// New attacks are refused and attackers are warned. Games get `SHUTDOWN_DRAIN_IN_SECONDS` to
+/// finish, after which the rest are checkpointed to be resumed on the next server, or settled
+/// if they cannot be saved. The HTTP server is stopped last.
+pub async fn shut_down_on_signal(
+    server: ServerHandle,
+    registry: Addr<GameRegistry>,
+    shutdown: Data<ShutdownFlag>,
+) {
+    wait_for_signal().await;
+    log::info!("Shutting down, draining live games");
+    shutdown.trigger();
+
+    let drain_time = Duration::from_secs(SHUTDOWN_DRAIN_IN_SECONDS);
+    let deadline = Instant::now() + drain_time;
+    if registry.send(DrainGames { drain_time }).await.is_ok() {
+        while Instant::now() < deadline {
+            match registry.send(ListGameIds).await {
+                Ok(game_ids) if !game_ids.is_empty() => {
+                    actix_rt::time::sleep(Duration::from_millis(500)).await
+                }
+                _ => break,
+            }
+        }
+        if registry.send(CheckpointGames).await.is_err() {
+            log::info!("Failed to checkpoint live games");
+        }
+    }
+
+    log::info!("Live games drained, stopping server");
+    server.stop(true).await;
+}
+
+async fn wait_for_signal() {
+    let mut terminate =
+        actix_rt::signal::unix::signal(actix_rt::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+    select(
+        Box::pin(actix_rt::signal::ctrl_c()),
+        Box::pin(terminate.recv()),
+    )
+    .await;
+}
@@ -1,27 +1,79 @@
-use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::constants::{
+    LEGACY_SOCKET_CAPABILITIES, LEGACY_SOCKET_PROTOCOL_VERSION, MSGPACK_CAPABILITY,
+    SOCKET_CAPABILITIES, SOCKET_PROTOCOL_VERSIONS,
+};
 
 // use crate::validator::util::Coords;
+use super::util::ResultResponse;
 use crate::{
     validator::util::Coords,
-    validator::util::{Attacker, BombType, BuildingDetails, DefenderDetails, MineDetails},
+    validator::util::{
+        Attacker, BombType, BuildingDetails, DefenderDetails, MineDetails, StateChecksum,
+    },
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SocketRequest {
     pub frame_number: i32,
     pub action_type: ActionType,
     pub attacker_id: Option<i32>,
+    pub attacker_instance_id: Option<i32>,
     pub bomb_id: Option<i32>,
     pub start_position: Option<Coords>,
     pub attacker_path: Vec<Coords>,
     pub bomb_position: Coords,
     pub is_game_over: Option<bool>,
+    pub bomb_loadout: Option<Vec<BombLoadout>>,
+    // The client's checksum for the latest checkpoint it was sent
+    pub state_checksum: Option<StateChecksum>,
+}
+
+/// Ordered actions for one or more frames, applied to the game as a single step.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchRequest {
+    pub actions: Vec<SocketRequest>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A message from the attacker: a single action or a batch of them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ClientMessage {
+    Batch(BatchRequest),
+    Single(SocketRequest),
+}
+
+impl ClientMessage {
+    /// The latest frame the message acts on.
+    pub fn frame_number(&self) -> i32 {
+        match self {
+            ClientMessage::Batch(batch) => batch
+                .actions
+                .iter()
+                .map(|action| action.frame_number)
+                .max()
+                .unwrap_or_default(),
+            ClientMessage::Single(request) => request.frame_number,
+        }
+    }
+}
+
+/// How many bombs of one EMP type the attacker brings into the game.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct BombLoadout {
+    pub bomb_id: i32,
+    pub count: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SocketResponse {
     pub frame_number: i32,
     pub result_type: ResultType,
+    pub attacker_instance_id: Option<i32>,
     pub is_alive: Option<bool>,
     pub attacker_health: Option<i32>,
     pub exploded_mines: Option<Vec<MineDetails>>,
@@ -32,12 +84,18 @@ pub struct SocketResponse {
     pub damaged_buildings: Option<Vec<BuildingResponse>>,
     pub total_damage_percentage: Option<f32>,
     pub is_sync: bool,
-    // pub state: Option<GameStateResponse>,
+    pub remaining_time_in_millis: Option<u64>,
+    pub error_code: Option<SocketErrorCode>,
+    pub state_checksum: Option<StateChecksum>,
+    // Only sent to resync a client whose checksum did not match
+    pub state: Option<GameStateResponse>,
     pub is_game_over: bool,
     pub message: Option<String>,
+    // Only sent with the game over, once the game is settled
+    pub result: Option<ResultResponse>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ActionType {
     IsMine,
     PlaceAttacker,
@@ -46,9 +104,10 @@ pub enum ActionType {
     Idle,
     Terminate,
     SelfDestruct,
+    SetLoadout,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ResultType {
     MinesExploded,
     DefendersDamaged,
@@ -58,6 +117,135 @@ pub enum ResultType {
     GameOver,
     PlacedAttacker,
     Nothing,
+    ClockSync,
+    GameResumed,
+    Handshake,
+    Error,
+    Batch,
+    ServerShutdown,
+}
+
+/// Sent with `ResultType::Error` to clients speaking protocol v2 or later.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SocketErrorCode {
+    MalformedMessage,
+    UnsupportedProtocolVersion,
+    InvalidAction,
+    InternalError,
+}
+
+/// First message of a client speaking protocol v2 or later. Clients that start with a bare
+/// `SocketRequest` are served protocol v1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandshakeRequest {
+    pub protocol_version: i32,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandshakeResponse {
+    pub result_type: ResultType,
+    pub protocol_version: i32,
+    pub capabilities: Vec<String>,
+}
+
+/// How messages after the handshake are carried: JSON in text frames, or MessagePack with the
+/// same field names in binary frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SocketEncoding {
+    Json,
+    MessagePack,
+}
+
+/// A message encoded for the socket, sent as a text or a binary frame.
+pub enum EncodedMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl SocketEncoding {
+    pub fn encode<T: Serialize>(self, message: &T) -> Result<EncodedMessage> {
+        match self {
+            SocketEncoding::Json => Ok(EncodedMessage::Text(serde_json::to_string(message)?)),
+            SocketEncoding::MessagePack => {
+                Ok(EncodedMessage::Binary(rmp_serde::to_vec_named(message)?))
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            SocketEncoding::Json => Ok(serde_json::from_slice(bytes)?),
+            SocketEncoding::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
+
+/// What the server and client agreed on during the handshake.
+pub struct SocketProtocol {
+    pub version: i32,
+    pub capabilities: HashSet<String>,
+}
+
+impl SocketProtocol {
+    /// Protocol v1: no handshake, plain text errors and every capability the server had
+    /// before the handshake existed.
+    pub fn legacy() -> SocketProtocol {
+        SocketProtocol {
+            version: LEGACY_SOCKET_PROTOCOL_VERSION,
+            capabilities: LEGACY_SOCKET_CAPABILITIES
+                .iter()
+                .map(|capability| capability.to_string())
+                .collect(),
+        }
+    }
+
+    /// Agrees on the requested version and the capabilities both sides support, or `None` if
+    /// the server does not speak that version.
+    pub fn negotiate(handshake: &HandshakeRequest) -> Option<SocketProtocol> {
+        if !SOCKET_PROTOCOL_VERSIONS.contains(&handshake.protocol_version) {
+            return None;
+        }
+        if handshake.protocol_version == LEGACY_SOCKET_PROTOCOL_VERSION {
+            return Some(SocketProtocol::legacy());
+        }
+        Some(SocketProtocol {
+            version: handshake.protocol_version,
+            capabilities: handshake
+                .capabilities
+                .iter()
+                .filter(|capability| SOCKET_CAPABILITIES.contains(&capability.as_str()))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    pub fn has_typed_errors(&self) -> bool {
+        self.version > LEGACY_SOCKET_PROTOCOL_VERSION
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+
+    pub fn encoding(&self) -> SocketEncoding {
+        if self.supports(MSGPACK_CAPABILITY) {
+            SocketEncoding::MessagePack
+        } else {
+            SocketEncoding::Json
+        }
+    }
+
+    pub fn handshake_response(&self) -> HandshakeResponse {
+        let mut capabilities: Vec<String> = self.capabilities.iter().cloned().collect();
+        capabilities.sort();
+        HandshakeResponse {
+            result_type: ResultType::Handshake,
+            protocol_version: self.version,
+            capabilities,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -89,14 +277,14 @@ pub struct ArtifactsResponse {
     pub amount: i32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GameStateResponse {
     pub frame_no: i32,
     pub attacker_user_id: i32,
     pub defender_user_id: i32,
-    pub attacker: Option<Attacker>,
+    pub attackers: HashMap<i32, Attacker>,
     pub attacker_death_count: i32,
-    pub bombs: BombType,
+    pub bombs: Vec<BombType>,
     pub damage_percentage: f32,
     pub artifacts: i32,
     pub defenders: Vec<DefenderDetails>,
@@ -104,3 +292,65 @@ pub struct GameStateResponse {
     pub buildings: Vec<BuildingDetails>,
     pub total_hp_buildings: i32,
 }
+
+/// Sent to spectators: the full game state once when they join, then every event the attacker
+/// receives.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum SpectatorMessage {
+    GameState(GameStateResponse),
+    Event(Box<SocketResponse>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CLOCK_SYNC_CAPABILITY;
+
+    fn handshake(json: &str) -> HandshakeRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn unknown_versions_are_refused() {
+        assert!(SocketProtocol::negotiate(&handshake(r#"{"protocol_version":0}"#)).is_none());
+        assert!(SocketProtocol::negotiate(&handshake(r#"{"protocol_version":99}"#)).is_none());
+    }
+
+    #[test]
+    fn v1_gets_the_legacy_protocol_whatever_it_asks_for() {
+        let protocol = SocketProtocol::negotiate(&handshake(
+            r#"{"protocol_version":1,"capabilities":["msgpack"]}"#,
+        ))
+        .unwrap();
+        assert_eq!(protocol.version, LEGACY_SOCKET_PROTOCOL_VERSION);
+        assert!(protocol.supports(CLOCK_SYNC_CAPABILITY));
+        assert!(!protocol.supports(MSGPACK_CAPABILITY));
+        assert!(!protocol.has_typed_errors());
+        assert_eq!(protocol.encoding(), SocketEncoding::Json);
+    }
+
+    #[test]
+    fn v2_keeps_only_the_capabilities_both_sides_support() {
+        let protocol = SocketProtocol::negotiate(&handshake(
+            r#"{"protocol_version":2,"capabilities":["msgpack","clock_sync","teleport"]}"#,
+        ))
+        .unwrap();
+        assert_eq!(protocol.version, 2);
+        assert!(protocol.has_typed_errors());
+        assert_eq!(protocol.encoding(), SocketEncoding::MessagePack);
+        assert_eq!(
+            protocol.handshake_response().capabilities,
+            vec![CLOCK_SYNC_CAPABILITY, MSGPACK_CAPABILITY]
+        );
+    }
+
+    #[test]
+    fn v2_without_capabilities_gets_none() {
+        let protocol = SocketProtocol::negotiate(&handshake(r#"{"protocol_version":2}"#)).unwrap();
+        assert!(protocol.capabilities.is_empty());
+        assert!(!protocol.supports(CLOCK_SYNC_CAPABILITY));
+        assert_eq!(protocol.encoding(), SocketEncoding::Json);
+        assert_eq!(protocol.handshake_response().protocol_version, 2);
+    }
+}
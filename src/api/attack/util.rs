@@ -9,32 +9,39 @@ use crate::api::game::util::UserDetail;
 use crate::api::inventory::util::{get_bank_map_space_id, get_block_id_of_bank, get_user_map_id};
 use crate::api::user::util::fetch_user;
 use crate::api::util::{
-    GameHistoryEntry, GameHistoryResponse, HistoryboardEntry, HistoryboardResponse,
+    lock_artifacts, record_artifact_movements, ArtifactMovement, GameHistoryEntry,
+    GameHistoryResponse, HistoryboardEntry, HistoryboardResponse, LedgerReason,
 };
 use crate::api::{self, RedisConn};
 use crate::constants::*;
 use crate::error::DieselError;
 use crate::models::{
     Artifact, AttackerType, AvailableBlocks, BlockCategory, BlockType, BuildingType, DefenderType,
-    EmpType, Game, LevelsFixture, MapLayout, MapSpaces, MineType, NewAttackerPath, NewGame, Prop,
-    User,
+    EmpType, Game, LevelsFixture, MapLayout, MapSpaces, MineType, NewAttackerPath, NewGame,
+    NewGameInvalidation, NewSimulationLog, Prop, User,
 };
 use crate::schema::{block_type, building_type, defender_type, map_spaces, prop, user};
 use crate::util::function;
+use crate::validator::state::State;
 use crate::validator::util::Coords;
-use crate::validator::util::{BombType, BuildingDetails, DefenderDetails, MineDetails};
+use crate::validator::util::{
+    lootable_artifacts, BombType, BuildingDetails, DefenderDetails, MineDetails, ValidationPolicy,
+};
 use ::serde::{Deserialize, Serialize};
 use anyhow::Result;
 use chrono;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use rand::seq::IteratorRandom;
 use redis::Commands;
 use std::collections::{HashMap, HashSet};
 use std::env;
+use std::io::Write;
 
-use super::socket::BuildingResponse;
+use super::socket::{BuildingResponse, GameStateResponse, SocketRequest, SocketResponse};
 
 #[derive(Debug, Serialize)]
 pub struct DefensePosition {
@@ -64,25 +71,15 @@ pub struct AttackToken {
     pub iat: usize,
     pub exp: usize,
 }
-#[derive(Serialize, Clone, Debug)]
-pub enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
-#[derive(Serialize, Clone, Debug)]
-pub struct EventResponse {
-    // pub attacker_initial_position: Option<Coords>,
-    pub attacker_id: Option<i32>,
-    pub bomb_id: Option<i32>,
-    pub coords: Coords,
-    pub direction: Direction,
-    pub is_bomb: bool,
+/// A validated request/response pair, in the order the server handled it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameEvent {
+    pub f: i32,            //frame_number
+    pub q: SocketRequest,  //request
+    pub s: SocketResponse, //response
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ResultResponse {
     pub d: i32,  //damage_done
     pub a: i32,  //artifacts_collected
@@ -92,18 +89,76 @@ pub struct ResultResponse {
     pub nd: i32, //new_defender_trophies
     pub oa: i32, //old_attacker_trophies
     pub od: i32, //old_defender_trophies
+    #[serde(default)]
+    pub ls: Vec<LootStored>, //loot_stored, per storage building of the attacker
+    #[serde(default)]
+    pub lu: i32, //loot_spilled_to_user_balance
+    #[serde(default)]
+    pub lx: i32, //loot_discarded
+}
+
+/// Loot put into one of the attacker's storage buildings.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LootStored {
+    pub m: i32, //map_space_id
+    pub a: i32, //artifacts
+}
+
+/// What becomes of loot that does not fit in the attacker's storage buildings.
+///
+/// Spilled loot only raises `user.artifacts`. The player's next base save has to place every
+/// artifact, so it lands in their buildings then; until that save `reconcile_artifacts` counts it
+/// as unplaced rather than as drift.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LootOverflow {
+    // Taken from the defender but lost
+    Discard,
+    // Added to the attacker's balance without a building
+    SpillToBalance,
+    // Left with the defender
+    Cap,
+}
+
+impl LootOverflow {
+    // LOOT_OVERFLOW_POLICY is one of "discard", "spill" or "cap"; defaults to "cap"
+    pub fn from_env() -> LootOverflow {
+        match env::var("LOOT_OVERFLOW_POLICY").as_deref() {
+            Ok("discard") => LootOverflow::Discard,
+            Ok("spill") => LootOverflow::SpillToBalance,
+            _ => LootOverflow::Cap,
+        }
+    }
 }
 
-#[derive(Serialize, Clone)]
+/// Replay of a single game, stored in `simulation_log` once the game is settled.
+///
+/// `v` is bumped to `REPLAY_LOG_VERSION` whenever the shape of this struct changes, so the
+/// frontend can tell the formats apart. Events in `e` are ordered and frame-stamped; scrubbing
+/// to a frame means replaying every event up to it against the base in `b`.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GameLog {
+    pub v: i32,                    //replay_log_version
     pub g: i32,                    //game_id
     pub a: User,                   //attacker
     pub d: User,                   //defender
     pub b: SimulationBaseResponse, //base
-    pub e: Vec<EventResponse>,     //events
+    pub e: Vec<GameEvent>,         //events
     pub r: ResultResponse,         //result
 }
 
+/// Everything a reconnecting attacker needs to pick the game up on a new socket.
+///
+/// Stored under `Game:<game_id>`; `session` is the connection that wrote it, so a socket that
+/// has been replaced by a reconnect can tell it no longer owns the game.
+#[derive(Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub session: i64,
+    pub started_at: i64, // unix millis at which the game clock started
+    pub state: State,
+    pub game_log: GameLog,
+    pub damaged_buildings: Vec<BuildingResponse>,
+}
+
 pub fn get_map_id(defender_id: &i32, conn: &mut PgConnection) -> Result<Option<i32>> {
     use crate::schema::map_layout;
     let map_id = map_layout::table
@@ -300,10 +355,20 @@ pub struct AttackResponse {
     pub bomb_types: Vec<EmpType>,
     pub shortest_paths: Option<Vec<ShortestPathResponse>>,
     pub obtainable_artifacts: i32,
+    pub lootable_buildings: Vec<LootableBuilding>,
     pub attack_token: String,
     pub game_id: i32,
 }
 
+/// Artifacts held by a building of the opponent, and how many destroying it would take.
+#[derive(Serialize)]
+pub struct LootableBuilding {
+    pub map_space_id: i32,
+    pub artifacts: i32,
+    pub loot_percentage: i32,
+    pub lootable_artifacts: i32,
+}
+
 pub fn get_random_opponent_id(
     attacker_id: i32,
     conn: &mut PgConnection,
@@ -461,21 +526,36 @@ pub fn get_game_id_from_redis(
     }
 }
 
+/// Drops the ongoing game locks of `game_id`, leaving alone any lock since taken by a newer game.
 pub fn delete_game_id_from_redis(
     attacker_id: i32,
     defender_id: i32,
+    game_id: i32,
     redis_conn: &mut RedisConn,
 ) -> Result<()> {
-    redis_conn
-        .del(format!("Attacker:{}", attacker_id))
+    release_game_lock(&format!("Attacker:{}", attacker_id), game_id, redis_conn)
         .map_err(|err| anyhow::anyhow!("Failed to delete attacker key: {}", err))?;
-    redis_conn
-        .del(format!("Defender:{}", defender_id))
+    release_game_lock(&format!("Defender:{}", defender_id), game_id, redis_conn)
         .map_err(|err| anyhow::anyhow!("Failed to delete defender key: {}", err))?;
 
     Ok(())
 }
 
+fn release_game_lock(key: &str, game_id: i32, redis_conn: &mut RedisConn) -> Result<()> {
+    redis::Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+    .key(key)
+    .arg(game_id)
+    .invoke::<i32>(&mut **redis_conn)?;
+    Ok(())
+}
+
 pub fn encode_attack_token(attacker_id: i32, defender_id: i32, game_id: i32) -> Result<String> {
     let jwt_secret = env::var("COOKIE_KEY").expect("COOKIE_KEY must be set!");
     let now = chrono::Local::now();
@@ -625,6 +705,7 @@ pub fn get_defenders(
             is_alive: true,
             damage_dealt: false,
             target_id: None,
+            target_attacker: None,
             path_in_current_frame: Vec::new(),
             block_id: block_type.id,
             level: defender.level,
@@ -670,6 +751,7 @@ pub fn get_buildings(conn: &mut PgConnection, map_id: i32) -> Result<Vec<Buildin
                 range: prop.range,
                 frequency: prop.frequency,
                 block_id: block_type.id,
+                loot_percentage: building.loot_percentage,
             },
         )
         .collect();
@@ -701,6 +783,7 @@ pub fn get_hut_defender(
             is_alive: true,
             damage_dealt: false,
             target_id: None,
+            target_attacker: None,
             path_in_current_frame: Vec::new(),
             block_id: block_type.id,
             level: defender_type.level,
@@ -735,9 +818,12 @@ pub fn get_hut_defender(
     Ok(hut_defenders_res)
 }
 
-pub fn get_bomb_types(conn: &mut PgConnection) -> Result<Vec<BombType>> {
-    use crate::schema::emp_type::dsl::*;
-    let bomb_types = emp_type
+pub fn get_bomb_types(conn: &mut PgConnection, attacker_id: i32) -> Result<Vec<BombType>> {
+    use crate::schema::{available_blocks, emp_type};
+    let bomb_types = emp_type::table
+        .inner_join(available_blocks::table)
+        .filter(available_blocks::user_id.eq(attacker_id))
+        .select(emp_type::all_columns)
         .load::<EmpType>(conn)
         .map_err(|err| DieselError {
             table: "emp_type",
@@ -787,18 +873,17 @@ pub fn update_buidling_artifacts(
     Ok(buildings)
 }
 
+/// Settles the game and clears it from Redis. Answers the result of the game if this call is
+/// the one that settled it.
 pub fn terminate_game(
     game_log: &mut GameLog,
+    game_state: &State,
     conn: &mut PgConnection,
     damaged_buildings: &[BuildingResponse],
     redis_conn: &mut RedisConn,
-) -> Result<()> {
-    use crate::schema::{artifact, game};
+) -> Result<Option<ResultResponse>> {
     let attacker_id = game_log.a.id;
     let defender_id = game_log.d.id;
-    let damage_done = game_log.r.d;
-    let bombs_used = game_log.r.b;
-    let artifacts_collected = game_log.r.a;
     let game_id = game_log.g;
     log::info!(
         "Terminating game for game:{} and attacker:{} and opponent:{}",
@@ -807,6 +892,109 @@ pub fn terminate_game(
         defender_id
     );
 
+    let is_settled =
+        conn.transaction(|conn| settle_game(game_log, game_state, damaged_buildings, conn))?;
+    if !is_settled {
+        log::info!(
+            "Game:{} of attacker:{} and opponent:{} was already settled",
+            game_id,
+            attacker_id,
+            defender_id
+        );
+    }
+
+    if delete_game_id_from_redis(game_log.a.id, game_log.d.id, game_id, redis_conn).is_err() {
+        log::info!(
+            "Can't remove game:{} and attacker:{} and opponent:{} from redis",
+            game_id,
+            attacker_id,
+            defender_id
+        );
+        return Err(anyhow::anyhow!("Can't remove game from redis"));
+    }
+
+    if delete_game_snapshot(game_id, redis_conn).is_err() {
+        log::info!("Can't remove snapshot of game:{} from redis", game_id);
+    }
+
+    log::info!(
+        "Game terminated successfully for game:{} and attacker:{} and opponent:{}",
+        game_id,
+        attacker_id,
+        defender_id
+    );
+
+    Ok(is_settled.then(|| game_log.r.clone()))
+}
+
+/// Pays out a game: its row, both players, the damaged buildings, the attacker's bank and the
+/// replay log. Meant to run in one transaction, so a game is settled in full or not at all.
+///
+/// The game row is locked first and a game already over is left as it is, so settling the same
+/// game twice pays out once. Answers whether this call settled it.
+fn settle_game(
+    game_log: &mut GameLog,
+    game_state: &State,
+    damaged_buildings: &[BuildingResponse],
+    conn: &mut PgConnection,
+) -> Result<bool> {
+    use crate::schema::{game, game_invalidation, simulation_log};
+    let attacker_id = game_log.a.id;
+    let defender_id = game_log.d.id;
+    let damage_done = game_log.r.d;
+    let bombs_used = game_log.r.b;
+    let game_id = game_log.g;
+
+    let is_game_over = game::table
+        .find(game_id)
+        .select(game::is_game_over)
+        .for_update()
+        .first::<bool>(conn)
+        .map_err(|err| DieselError {
+            table: "game",
+            function: function!(),
+            error: err,
+        })?;
+    if is_game_over {
+        return Ok(false);
+    }
+
+    let is_voided = game_state.validation_policy == ValidationPolicy::VoidResult
+        && !game_state.invalidations.is_empty();
+    let payout = if is_voided {
+        LootPayout::default()
+    } else {
+        pay_out_loot(
+            attacker_id,
+            defender_id,
+            game_id,
+            game_log.r.a,
+            damaged_buildings,
+            conn,
+        )?
+    };
+    let artifacts_collected = payout.credited();
+    let policy = game_state.validation_policy.as_str();
+    let new_invalidations: Vec<NewGameInvalidation> = game_state
+        .invalidations
+        .iter()
+        .map(|record| NewGameInvalidation {
+            game_id: &game_id,
+            reason: &record.message,
+            frame_number: &record.frame_number,
+            policy,
+        })
+        .collect();
+
+    diesel::insert_into(game_invalidation::table)
+        .values(&new_invalidations)
+        .execute(conn)
+        .map_err(|err| DieselError {
+            table: "game_invalidation",
+            function: function!(),
+            error: err,
+        })?;
+
     let (attack_score, defense_score) = if damage_done < WIN_THRESHOLD {
         (damage_done - 100, 100 - damage_done)
     } else {
@@ -834,12 +1022,22 @@ pub fn terminate_game(
     let attack_score = attack_score as f32 / 100_f32;
     let defence_score = defense_score as f32 / 100_f32;
 
-    let new_trophies = new_rating(
-        attacker_details.trophies,
-        defender_details.trophies,
-        attack_score,
-        defence_score,
-    );
+    let new_trophies = if is_voided {
+        log::info!(
+            "Voiding result of game:{} for attacker:{} and opponent:{}",
+            game_id,
+            attacker_id,
+            defender_id
+        );
+        (attacker_details.trophies, defender_details.trophies)
+    } else {
+        new_rating(
+            attacker_details.trophies,
+            defender_details.trophies,
+            attack_score,
+            defence_score,
+        )
+    };
 
     //Add bonus trophies (just call the function)
 
@@ -847,6 +1045,10 @@ pub fn terminate_game(
     game_log.r.od = defender_details.trophies;
     game_log.r.na = new_trophies.0;
     game_log.r.nd = new_trophies.1;
+    game_log.r.a = artifacts_collected;
+    game_log.r.ls = payout.stored.clone();
+    game_log.r.lu = payout.spilled;
+    game_log.r.lx = payout.discarded;
 
     diesel::update(game::table.find(game_id))
         .set((
@@ -864,103 +1066,189 @@ pub fn terminate_game(
             error: err,
         })?;
 
-    let (attacker_wins, defender_wins) = if damage_done < WIN_THRESHOLD {
-        (0, 1)
-    } else {
-        (1, 0)
-    };
+    if !is_voided {
+        let (attacker_wins, defender_wins) = if damage_done < WIN_THRESHOLD {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
 
-    diesel::update(user::table.find(&game_log.a.id))
-        .set((
-            user::artifacts.eq(user::artifacts + artifacts_collected),
-            user::trophies.eq(user::trophies + new_trophies.0 - attacker_details.trophies),
-            user::attacks_won.eq(user::attacks_won + attacker_wins),
-        ))
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "game",
-            function: function!(),
-            error: err,
-        })?;
+        diesel::update(user::table.find(&game_log.a.id))
+            .set((
+                user::trophies.eq(user::trophies + new_trophies.0 - attacker_details.trophies),
+                user::attacks_won.eq(user::attacks_won + attacker_wins),
+            ))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "game",
+                function: function!(),
+                error: err,
+            })?;
 
-    if deduct_artifacts_from_building(damaged_buildings.to_vec(), conn).is_err() {
-        log::info!(
-            "Failed to deduct artifacts from building for game:{} and attacker:{} and opponent:{}",
-            game_id,
-            attacker_id,
-            defender_id
-        );
+        diesel::update(user::table.find(&game_log.d.id))
+            .set((
+                user::trophies.eq(user::trophies + new_trophies.1 - defender_details.trophies),
+                user::defenses_won.eq(user::defenses_won + defender_wins),
+            ))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "game",
+                function: function!(),
+                error: err,
+            })?;
     }
-    diesel::update(user::table.find(&game_log.d.id))
-        .set((
-            user::artifacts.eq(user::artifacts - artifacts_collected),
-            user::trophies.eq(user::trophies + new_trophies.1 - defender_details.trophies),
-            user::defenses_won.eq(user::defenses_won + defender_wins),
-        ))
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "game",
-            function: function!(),
-            error: err,
-        })?;
 
-    let attacker_map_id = get_user_map_id(attacker_id, conn)?;
-    let attacker_bank_block_type_id = get_block_id_of_bank(conn, &attacker_id)?;
-    let attacker_bank_map_space_id =
-        get_bank_map_space_id(conn, &attacker_map_id, &attacker_bank_block_type_id)?;
+    let sim_log = encode_game_log(game_log)?;
+    let new_simulation_log = NewSimulationLog {
+        game_id: &game_id,
+        log_text: &sim_log,
+    };
 
-    diesel::update(artifact::table.find(attacker_bank_map_space_id))
-        .set(artifact::count.eq(artifact::count + artifacts_collected))
+    diesel::insert_into(simulation_log::table)
+        .values(new_simulation_log)
+        .on_conflict_do_nothing()
         .execute(conn)
         .map_err(|err| DieselError {
-            table: "artifact",
+            table: "simulation_log",
             function: function!(),
             error: err,
         })?;
 
-    // if let Ok(sim_log) = serde_json::to_string(&game_log) {
-    //     let new_simulation_log = NewSimulationLog {
-    //         game_id: &game_id,
-    //         log_text: &sim_log,
-    //     };
-
-    //     println!("Inserting into similation log, game id: {}", game_id);
-    //     diesel::insert_into(simulation_log::table)
-    //         .values(new_simulation_log)
-    //         .on_conflict_do_nothing()
-    //         .execute(conn)
-    //         .map_err(|err| DieselError {
-    //             table: "simulation_log",
-    //             function: function!(),
-    //             error: err,
-    //         })?;
-    //     println!("Done Inserting into similation log, game id: {}", game_id);
-    // }
-
-    if delete_game_id_from_redis(game_log.a.id, game_log.d.id, redis_conn).is_err() {
-        log::info!(
-            "Can't remove game:{} and attacker:{} and opponent:{} from redis",
-            game_id,
-            attacker_id,
-            defender_id
-        );
-        return Err(anyhow::anyhow!("Can't remove game from redis"));
-    }
+    Ok(true)
+}
 
-    // for event in game_log.events.iter() {
-    //     println!("Event: {:?}\n", event);
-    // }
+/// A response sent to the attacker, as published to spectators on `Spectate:<game_id>`.
+///
+/// `seq` is the number of events in the game log when it was sent; spectators skip events
+/// already covered by the `SpectatorState` they joined with.
+#[derive(Serialize, Deserialize)]
+pub struct SpectatorEvent {
+    pub seq: usize,
+    pub event: SocketResponse,
+}
 
-    log::info!(
-        "Game terminated successfully for game:{} and attacker:{} and opponent:{}",
-        game_id,
-        attacker_id,
-        defender_id
-    );
+/// Latest state of a game being played, stored under `SpectateState:<game_id>` for spectators
+/// joining mid-game. Like the attacker, spectators are not shown the mines not yet blown.
+#[derive(Serialize, Deserialize)]
+pub struct SpectatorState {
+    pub seq: usize,
+    pub state: GameStateResponse,
+}
+
+pub fn publish_game_event(
+    seq: usize,
+    game_id: i32,
+    state: GameStateResponse,
+    response: &SocketResponse,
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    let state_json = serde_json::to_string(&SpectatorState { seq, state })?;
+    let event_json = serde_json::to_string(&SpectatorEvent {
+        seq,
+        event: response.clone(),
+    })?;
+    redis::pipe()
+        .atomic()
+        .set_ex(
+            format!("SpectateState:{}", game_id),
+            state_json,
+            game_snapshot_age_in_seconds(),
+        )
+        .ignore()
+        .publish(format!("Spectate:{}", game_id), event_json)
+        .ignore()
+        .query::<()>(&mut **redis_conn)
+        .map_err(|err| anyhow::anyhow!("Failed to publish game event: {}", err))?;
+    Ok(())
+}
+
+// A game's whole lifetime, reconnect window included, plus two reaper runs, so the reaper still
+// finds the snapshot of a game whose server went down
+fn game_snapshot_age_in_seconds() -> usize {
+    game_lifetime_in_seconds() as usize + 2 * STALE_GAME_REAPER_INTERVAL_IN_SECONDS as usize
+}
+
+/// How long a started game can run, counting the reconnect window after its socket drops.
+fn game_lifetime_in_seconds() -> i64 {
+    GAME_AGE_IN_MINUTES as i64 * 60 + RECONNECT_GRACE_IN_SECONDS as i64
+}
 
+/// Starts a new connection for the game and returns its session number.
+pub fn start_game_session(game_id: i32, redis_conn: &mut RedisConn) -> Result<i64> {
+    let key = format!("Session:{}", game_id);
+    let session: i64 = redis_conn
+        .incr(&key, 1)
+        .map_err(|err| anyhow::anyhow!("Failed to start game session: {}", err))?;
+    redis_conn
+        .expire::<_, ()>(&key, game_snapshot_age_in_seconds())
+        .map_err(|err| anyhow::anyhow!("Failed to set game session expiry: {}", err))?;
+    Ok(session)
+}
+
+pub fn get_game_session(game_id: i32, redis_conn: &mut RedisConn) -> Result<Option<i64>> {
+    let session: Option<i64> = redis_conn
+        .get(format!("Session:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to get game session: {}", err))?;
+    Ok(session)
+}
+
+pub fn save_game_snapshot(
+    session: i64,
+    started_at: i64,
+    game_state: &State,
+    game_log: &GameLog,
+    damaged_buildings: &[BuildingResponse],
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    let snapshot = GameSnapshot {
+        session,
+        started_at,
+        state: game_state.clone(),
+        game_log: game_log.clone(),
+        damaged_buildings: damaged_buildings.to_vec(),
+    };
+    let snapshot_json = serde_json::to_string(&snapshot)?;
+    redis_conn
+        .set_ex::<_, _, ()>(
+            format!("Game:{}", game_log.g),
+            snapshot_json,
+            game_snapshot_age_in_seconds(),
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to save game snapshot: {}", err))?;
     Ok(())
 }
 
+pub fn get_game_snapshot(game_id: i32, redis_conn: &mut RedisConn) -> Result<Option<GameSnapshot>> {
+    let snapshot_json: Option<String> = redis_conn
+        .get(format!("Game:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to get game snapshot: {}", err))?;
+    match snapshot_json {
+        Some(snapshot_json) => Ok(Some(serde_json::from_str(&snapshot_json)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn delete_game_snapshot(game_id: i32, redis_conn: &mut RedisConn) -> Result<()> {
+    redis_conn
+        .del::<_, ()>(format!("Game:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to delete game snapshot: {}", err))?;
+    redis_conn
+        .del::<_, ()>(format!("Session:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to delete game session: {}", err))?;
+    redis_conn
+        .del::<_, ()>(format!("SpectateState:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to delete spectator state: {}", err))?;
+    Ok(())
+}
+
+/// Serializes a game log for `simulation_log`: gzipped JSON, base64 encoded to fit a text column.
+pub fn encode_game_log(game_log: &GameLog) -> Result<String> {
+    let log_json = serde_json::to_vec(game_log)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&log_json)?;
+    Ok(base64::encode(encoder.finish()?))
+}
+
 pub fn check_and_remove_incomplete_game(
     attacker_id: &i32,
     defender_id: &i32,
@@ -999,6 +1287,207 @@ pub fn check_and_remove_incomplete_game(
     Ok(())
 }
 
+/// Settles or voids games that were never settled, e.g. because their server went down
+/// mid-attack, and drops the ongoing game locks left behind for them.
+///
+/// Games in `live_games` are still being played here and are left alone. A game with a
+/// snapshot is settled from it once its clock has run out; one with nothing left to settle
+/// from is voided once its attack token and clock could no longer be running.
+pub fn reap_stale_games(
+    live_games: &HashSet<i32>,
+    conn: &mut PgConnection,
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    use crate::schema::game;
+    use diesel::dsl::{now, IntervalDsl};
+
+    let snapshot_keys: Vec<String> = redis_conn
+        .scan_match::<_, String>("Game:*")
+        .map_err(|err| anyhow::anyhow!("Failed to scan game snapshots: {}", err))?
+        .collect();
+    for game_id in snapshot_keys
+        .iter()
+        .filter_map(|key| key.strip_prefix("Game:")?.parse::<i32>().ok())
+        .filter(|game_id| !live_games.contains(game_id))
+    {
+        if let Err(err) = settle_stale_snapshot(game_id, conn, redis_conn) {
+            log::info!("Failed to settle stale game:{}: {:?}", game_id, err);
+        }
+    }
+
+    let stale_after = (ATTACK_TOKEN_AGE_IN_MINUTES * 60 + game_lifetime_in_seconds()) as i32;
+    let stale_games = game::table
+        .filter(game::is_game_over.eq(false))
+        .filter(game::created_at.lt(now - stale_after.seconds()))
+        .load::<Game>(conn)
+        .map_err(|err| DieselError {
+            table: "game",
+            function: function!(),
+            error: err,
+        })?;
+    for stale_game in stale_games
+        .iter()
+        .filter(|stale_game| !live_games.contains(&stale_game.id))
+    {
+        // Settled from its snapshot on a later run, once its clock runs out
+        if get_game_snapshot(stale_game.id, redis_conn).is_ok_and(|snapshot| snapshot.is_some()) {
+            continue;
+        }
+        if let Err(err) = void_stale_game(stale_game, conn, redis_conn) {
+            log::info!("Failed to void stale game:{}: {:?}", stale_game.id, err);
+        }
+    }
+
+    for lock_key in ["Attacker:*", "Defender:*"] {
+        let lock_keys: Vec<String> = redis_conn
+            .scan_match::<_, String>(lock_key)
+            .map_err(|err| anyhow::anyhow!("Failed to scan game locks: {}", err))?
+            .collect();
+        for lock_key in lock_keys {
+            if let Err(err) = release_stale_game_lock(&lock_key, live_games, conn, redis_conn) {
+                log::info!("Failed to release stale lock {}: {:?}", lock_key, err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn settle_stale_snapshot(
+    game_id: i32,
+    conn: &mut PgConnection,
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    use crate::schema::game;
+
+    let Some(mut snapshot) = get_game_snapshot(game_id, redis_conn)? else {
+        return Ok(());
+    };
+    let stale_at = snapshot.started_at + game_lifetime_in_seconds() * 1000;
+    if chrono::Utc::now().timestamp_millis() < stale_at {
+        return Ok(());
+    }
+
+    let is_game_over = game::table
+        .find(game_id)
+        .select(game::is_game_over)
+        .first::<bool>(conn)
+        .optional()
+        .map_err(|err| DieselError {
+            table: "game",
+            function: function!(),
+            error: err,
+        })?;
+    if is_game_over != Some(false) {
+        return delete_game_snapshot(game_id, redis_conn);
+    }
+
+    log::info!(
+        "Settling stale game:{} of attacker:{} and opponent:{} from its snapshot",
+        game_id,
+        snapshot.game_log.a.id,
+        snapshot.game_log.d.id
+    );
+    terminate_game(
+        &mut snapshot.game_log,
+        &snapshot.state,
+        conn,
+        &snapshot.damaged_buildings,
+        redis_conn,
+    )?;
+    Ok(())
+}
+
+fn void_stale_game(
+    stale_game: &Game,
+    conn: &mut PgConnection,
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    use crate::schema::{game, game_invalidation};
+
+    log::info!(
+        "Voiding stale game:{} of attacker:{} and opponent:{}",
+        stale_game.id,
+        stale_game.attack_id,
+        stale_game.defend_id
+    );
+    conn.transaction(|conn| {
+        // Settled meanwhile by its own server
+        let voided = diesel::update(
+            game::table
+                .find(stale_game.id)
+                .filter(game::is_game_over.eq(false)),
+        )
+        .set((
+            game::is_game_over.eq(true),
+            game::attack_score.eq(0),
+            game::defend_score.eq(0),
+            game::artifacts_collected.eq(0),
+        ))
+        .execute(conn)?;
+        if voided == 0 {
+            return Ok(());
+        }
+        diesel::insert_into(game_invalidation::table)
+            .values(NewGameInvalidation {
+                game_id: &stale_game.id,
+                reason: "Game was abandoned",
+                frame_number: &0,
+                policy: ValidationPolicy::VoidResult.as_str(),
+            })
+            .execute(conn)?;
+        Ok(())
+    })
+    .map_err(|err| DieselError {
+        table: "game",
+        function: function!(),
+        error: err,
+    })?;
+
+    delete_game_id_from_redis(
+        stale_game.attack_id,
+        stale_game.defend_id,
+        stale_game.id,
+        redis_conn,
+    )
+}
+
+// A lock is stale once its game is over or gone
+fn release_stale_game_lock(
+    lock_key: &str,
+    live_games: &HashSet<i32>,
+    conn: &mut PgConnection,
+    redis_conn: &mut RedisConn,
+) -> Result<()> {
+    use crate::schema::game;
+
+    let Some(game_id) = redis_conn
+        .get::<_, Option<i32>>(lock_key)
+        .map_err(|err| anyhow::anyhow!("Failed to get key: {}", err))?
+    else {
+        return Ok(());
+    };
+    if live_games.contains(&game_id) {
+        return Ok(());
+    }
+
+    let is_game_over = game::table
+        .find(game_id)
+        .select(game::is_game_over)
+        .first::<bool>(conn)
+        .optional()
+        .map_err(|err| DieselError {
+            table: "game",
+            function: function!(),
+            error: err,
+        })?;
+    if is_game_over != Some(false) {
+        log::info!("Releasing stale lock {} of game:{}", lock_key, game_id);
+        release_game_lock(lock_key, game_id, redis_conn)?;
+    }
+    Ok(())
+}
+
 pub fn can_attack_happen(conn: &mut PgConnection, user_id: i32, is_attacker: bool) -> Result<bool> {
     use crate::schema::game::dsl::*;
 
@@ -1033,47 +1522,194 @@ pub fn can_attack_happen(conn: &mut PgConnection, user_id: i32, is_attacker: boo
     }
 }
 
-pub fn deduct_artifacts_from_building(
-    damaged_buildings: Vec<BuildingResponse>,
+/// Where the loot of a game went.
+#[derive(Default)]
+struct LootPayout {
+    // Taken out of the defender's buildings
+    taken: i32,
+    stored: Vec<LootStored>,
+    spilled: i32,
+    discarded: i32,
+}
+
+impl LootPayout {
+    /// What the attacker is credited with.
+    fn credited(&self) -> i32 {
+        self.taken - self.discarded
+    }
+}
+
+/// Moves the loot of a game from the defender's damaged buildings into the attacker's storage
+/// buildings, bank first, each up to its capacity, and from the defender's `user.artifacts` to
+/// the attacker's. Both players and every building involved are locked, and no building gives
+/// up more than it still holds.
+///
+/// Loot that does not fit is handled as `LOOT_OVERFLOW_POLICY` says.
+fn pay_out_loot(
+    attacker_id: i32,
+    defender_id: i32,
+    game_id: i32,
+    artifacts_collected: i32,
+    damaged_buildings: &[BuildingResponse],
     conn: &mut PgConnection,
-) -> Result<()> {
-    use crate::schema::artifact;
-    for building in damaged_buildings.iter() {
-        if (building.artifacts_if_damaged) > 0 {
-            diesel::update(artifact::table.find(building.id))
-                .set(artifact::count.eq(artifact::count - building.artifacts_if_damaged))
-                .execute(conn)
-                .map_err(|err| DieselError {
-                    table: "artifact",
-                    function: function!(),
-                    error: err,
-                })?;
+) -> Result<LootPayout> {
+    let overflow = LootOverflow::from_env();
+    let attacker_map_id = get_user_map_id(attacker_id, conn)?;
+    let attacker_bank_block_type_id = get_block_id_of_bank(conn, &attacker_id)?;
+    let attacker_bank_map_space_id =
+        get_bank_map_space_id(conn, &attacker_map_id, &attacker_bank_block_type_id)?;
+    let storage_map_space_ids =
+        get_storage_map_space_ids(attacker_map_id, attacker_bank_map_space_id, conn)?;
+
+    let looted_buildings: Vec<&BuildingResponse> = damaged_buildings
+        .iter()
+        .filter(|building| building.artifacts_if_damaged > 0)
+        .collect();
+    let map_space_ids: Vec<i32> = looted_buildings
+        .iter()
+        .map(|building| building.id)
+        .chain(storage_map_space_ids.iter().copied())
+        .collect();
+    let mut locked = lock_artifacts(conn, &[attacker_id, defender_id], &map_space_ids)?;
+
+    let storage_room: i32 = storage_map_space_ids
+        .iter()
+        .map(|map_space_id| locked.room(*map_space_id))
+        .sum();
+    let mut remaining = match overflow {
+        LootOverflow::Cap => artifacts_collected.min(storage_room),
+        LootOverflow::Discard | LootOverflow::SpillToBalance => artifacts_collected,
+    };
+
+    let mut payout = LootPayout::default();
+    let mut movements = Vec::new();
+    for building in looted_buildings {
+        let amount = building
+            .artifacts_if_damaged
+            .min(locked.count(building.id))
+            .min(remaining);
+        if amount <= 0 {
+            continue;
         }
+        locked.withdraw(conn, building.id, amount)?;
+        remaining -= amount;
+        payout.taken += amount;
+        movements.push(ArtifactMovement {
+            user_id: defender_id,
+            source: Some(building.id),
+            destination: None,
+            amount,
+            reason: LedgerReason::AttackLoss,
+            game_id: Some(game_id),
+            request_id: None,
+        });
     }
-    Ok(())
+
+    let mut unstored = payout.taken;
+    for map_space_id in storage_map_space_ids {
+        let amount = locked.room(map_space_id).min(unstored);
+        if amount <= 0 {
+            continue;
+        }
+        locked.deposit(conn, map_space_id, amount)?;
+        unstored -= amount;
+        payout.stored.push(LootStored {
+            m: map_space_id,
+            a: amount,
+        });
+        movements.push(ArtifactMovement {
+            user_id: attacker_id,
+            source: None,
+            destination: Some(map_space_id),
+            amount,
+            reason: LedgerReason::AttackLoot,
+            game_id: Some(game_id),
+            request_id: None,
+        });
+    }
+
+    if overflow == LootOverflow::SpillToBalance {
+        payout.spilled = unstored;
+        if unstored > 0 {
+            movements.push(ArtifactMovement {
+                user_id: attacker_id,
+                source: None,
+                destination: None,
+                amount: unstored,
+                reason: LedgerReason::AttackLoot,
+                game_id: Some(game_id),
+                request_id: None,
+            });
+        }
+    } else {
+        payout.discarded = unstored;
+    }
+    locked.earn(conn, attacker_id, payout.credited())?;
+    // Taken off as is, as `spend` would hold up the whole settlement over a balance that drifted
+    locked.earn(conn, defender_id, -payout.taken)?;
+    record_artifact_movements(&movements, conn)?;
+
+    Ok(payout)
 }
 
-pub fn artifacts_obtainable_from_base(map_id: i32, conn: &mut PgConnection) -> Result<i32> {
-    use crate::schema::{artifact, map_spaces};
+/// Map spaces of the base that can hold artifacts, the bank first and the rest in id order.
+fn get_storage_map_space_ids(
+    map_id: i32,
+    bank_map_space_id: i32,
+    conn: &mut PgConnection,
+) -> Result<Vec<i32>> {
+    let others: Vec<i32> = map_spaces::table
+        .inner_join(block_type::table.inner_join(building_type::table))
+        .filter(map_spaces::map_id.eq(map_id))
+        .filter(map_spaces::id.ne(bank_map_space_id))
+        .filter(building_type::capacity.gt(0))
+        .order_by(map_spaces::id)
+        .select(map_spaces::id)
+        .load::<i32>(conn)
+        .map_err(|err| DieselError {
+            table: "map_spaces",
+            function: function!(),
+            error: err,
+        })?;
+    Ok(std::iter::once(bank_map_space_id).chain(others).collect())
+}
 
-    let mut artifacts = 0;
+/// What destroying each building of a base would take, worked out with the same rule as
+/// `bomb_blast`. Buildings holding no artifacts are left out.
+pub fn get_lootable_buildings(
+    map_id: i32,
+    conn: &mut PgConnection,
+) -> Result<Vec<LootableBuilding>> {
+    use crate::schema::artifact;
 
-    for (_, count) in map_spaces::table
-        .left_join(artifact::table)
+    let lootable_buildings = map_spaces::table
+        .inner_join(artifact::table)
+        .inner_join(block_type::table.inner_join(building_type::table))
         .filter(map_spaces::map_id.eq(map_id))
-        .select((map_spaces::all_columns, artifact::count.nullable()))
-        .load::<(MapSpaces, Option<i32>)>(conn)
+        .filter(building_type::id.ne(ROAD_ID))
+        .order_by(map_spaces::id)
+        .select((
+            map_spaces::id,
+            artifact::count,
+            building_type::loot_percentage,
+        ))
+        .load::<(i32, i32, i32)>(conn)
         .map_err(|err| DieselError {
             table: "map_spaces",
             function: function!(),
             error: err,
         })?
         .into_iter()
-    {
-        if let Some(count) = count {
-            artifacts += (count as f32 * PERCENTANGE_ARTIFACTS_OBTAINABLE).floor() as i32;
-        }
-    }
+        .filter(|(_, count, _)| *count > 0)
+        .map(
+            |(map_space_id, artifacts, loot_percentage)| LootableBuilding {
+                map_space_id,
+                artifacts,
+                loot_percentage,
+                lootable_artifacts: lootable_artifacts(artifacts, loot_percentage),
+            },
+        )
+        .collect();
 
-    Ok(artifacts)
+    Ok(lootable_buildings)
 }
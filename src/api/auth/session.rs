@@ -62,3 +62,34 @@ impl FromRequest for AuthUser {
         }
     }
 }
+
+/// A logged in user whose id is listed in `ADMIN_USER_IDS`.
+pub struct AdminUser(pub i32);
+
+impl AdminUser {
+    // ADMIN_USER_IDS is a comma separated list of user ids; nobody is an admin if it is unset
+    fn is_admin(user_id: i32) -> bool {
+        env::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|id| id.trim().parse::<i32>().ok())
+            .any(|id| id == user_id)
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user_id = match AuthUser::from_request(req, payload).into_inner() {
+            Ok(AuthUser(user_id)) => user_id,
+            Err(err) => return ready(Err(err)),
+        };
+        if Self::is_admin(user_id) {
+            ready(Ok(AdminUser(user_id)))
+        } else {
+            ready(Err(Self::Error::NotAdmin))
+        }
+    }
+}
@@ -2,12 +2,13 @@ use self::util::{DefenderTypeResponse, MineTypeResponse};
 
 use super::attack::util::get_game_id_from_redis;
 use super::auth::session::AuthUser;
+use super::idempotency::{run_idempotent, IdempotencyKey};
 use super::inventory::util::get_user_artifacts;
 use super::user::util::fetch_user;
 use super::PgPool;
 use super::RedisPool;
 use crate::api::error;
-use crate::api::util::HistoryboardQuery;
+use crate::api::util::{new_request_id, HistoryboardQuery};
 use crate::models::*;
 use actix_web::error::{ErrorBadRequest, ErrorNotFound};
 use actix_web::web::{self, Data, Json};
@@ -43,7 +44,7 @@ pub struct MapSpacesEntry {
     pub artifacts: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct TransferArtifactEntry {
     pub artifacts_differ: i32,
     pub map_space_id: i32,
@@ -57,7 +58,7 @@ pub struct TransferArtifactResponse {
     pub artifacts_in_bank: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct BatchTransferArtifacts {
     pub transfers: Vec<TransferArtifactEntry>,
 }
@@ -67,9 +68,26 @@ async fn post_transfer_artifacts(
     pg_pool: Data<PgPool>,
     redis_pool: Data<RedisPool>,
     user: AuthUser,
+    idempotency_key: IdempotencyKey,
 ) -> Result<impl Responder> {
     let user_id = user.0;
+    let request_id = idempotency_key.request_id();
+    run_idempotent(
+        &redis_pool.clone(),
+        user_id,
+        idempotency_key.for_body(&*transfer),
+        || transfer_artifacts(transfer, pg_pool, redis_pool, user_id, request_id),
+    )
+    .await
+}
 
+async fn transfer_artifacts(
+    transfer: Json<TransferArtifactEntry>,
+    pg_pool: Data<PgPool>,
+    redis_pool: Data<RedisPool>,
+    user_id: i32,
+    request_id: String,
+) -> Result<TransferArtifactResponse> {
     let mut redis_conn = redis_pool
         .get()
         .map_err(|err| error::handle_error(err.into()))?;
@@ -146,13 +164,8 @@ async fn post_transfer_artifacts(
     .await?
     .map_err(|err| error::handle_error(err.into()))?;
 
+    // A building without an artifact row holds none
     if building_artifact_count == -1 {
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        web::block(move || util::create_artifact_record(&mut conn, &transfer.map_space_id, &0))
-            .await?
-            .map_err(|err| error::handle_error(err.into()))?;
         building_artifact_count = 0;
     }
 
@@ -172,31 +185,29 @@ async fn post_transfer_artifacts(
         return Err(ErrorBadRequest("Building capacity not sufficient"));
     }
 
-    let new_building_artifact_count = building_artifact_count + transfer.artifacts_differ;
-    let new_bank_artifact_count = bank_artifact_count - transfer.artifacts_differ;
-
-    //Transfer Artifacts
+    //Transfer Artifacts, checked again under the lock
     let mut conn = pg_pool
         .get()
         .map_err(|err| error::handle_error(err.into()))?;
-    web::block(move || {
+    let (new_building_artifact_count, new_bank_artifact_count) = web::block(move || {
         util::transfer_artifacts_building(
             &mut conn,
+            &user_id,
             &transfer.map_space_id,
             &bank_map_space_id,
-            &new_building_artifact_count,
-            &new_bank_artifact_count,
+            &transfer.artifacts_differ,
+            &request_id,
         )
     })
     .await?
-    .map_err(|err| error::handle_error(err.into()))?;
+    .map_err(error::handle_artifact_error)?;
 
-    Ok(web::Json(TransferArtifactResponse {
+    Ok(TransferArtifactResponse {
         building_map_space_id: transfer.map_space_id,
         artifacts_in_building: new_building_artifact_count,
         bank_map_space_id,
         artifacts_in_bank: new_bank_artifact_count,
-    }))
+    })
 }
 
 async fn post_batch_transfer_artifacts(
@@ -204,8 +215,26 @@ async fn post_batch_transfer_artifacts(
     pg_pool: Data<PgPool>,
     redis_pool: Data<RedisPool>,
     user: AuthUser,
+    idempotency_key: IdempotencyKey,
 ) -> Result<impl Responder> {
     let user_id = user.0;
+    let request_id = idempotency_key.request_id();
+    run_idempotent(
+        &redis_pool.clone(),
+        user_id,
+        idempotency_key.for_body(&*batch_transfer),
+        || batch_transfer_artifacts(batch_transfer, pg_pool, redis_pool, user_id, request_id),
+    )
+    .await
+}
+
+async fn batch_transfer_artifacts(
+    batch_transfer: Json<BatchTransferArtifacts>,
+    pg_pool: Data<PgPool>,
+    redis_pool: Data<RedisPool>,
+    user_id: i32,
+    request_id: String,
+) -> Result<Vec<TransferArtifactResponse>> {
     let mut redis_conn = redis_pool
         .get()
         .map_err(|err| error::handle_error(err.into()))?;
@@ -217,19 +246,16 @@ async fn post_batch_transfer_artifacts(
     }
 
     let transfers = batch_transfer.into_inner().transfers;
-    let total_artifact_differ: i32 = transfers.iter().map(|transfer| transfer.artifacts_differ).sum();    
-    let mut responses = Vec::new();
-    let mut accum_val: i32 = 0;
+    let mut conn = pg_pool
+        .get()
+        .map_err(|err| error::handle_error(err.into()))?;
+    let bank_block_type_id = web::block(move || util::get_block_id_of_bank(&mut conn, &user_id))
+        .await?
+        .map_err(|err| error::handle_error(err.into()))?;
 
+    // Artifact counts and capacities are only checked under the lock, while the batch runs
+    let mut moves = Vec::new();
     for transfer in transfers {
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        let bank_block_type_id =
-            web::block(move || util::get_block_id_of_bank(&mut conn, &user_id))
-                .await?
-                .map_err(|err| error::handle_error(err.into()))?;
-
         let mut conn = pg_pool
             .get()
             .map_err(|err| error::handle_error(err.into()))?;
@@ -267,81 +293,40 @@ async fn post_batch_transfer_artifacts(
             return Err(ErrorBadRequest("Cannot transfer to the same building"));
         }
 
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        let bank_artifact_count = web::block(move || {
-            util::get_building_artifact_count(&mut conn, &current_layout_id, &bank_map_space_id)
-        })
-        .await?
-        .map_err(|err| error::handle_error(err.into()))?;
-        
-        if total_artifact_differ > bank_artifact_count+accum_val {
-            return Err(ErrorBadRequest("Not enough artifacts in the bank"));
-        }
-
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        let mut building_artifact_count = web::block(move || {
-            util::get_building_artifact_count(&mut conn, &current_layout_id, &transfer.map_space_id)
-        })
-        .await?
-        .map_err(|err| error::handle_error(err.into()))?;
-
-        if building_artifact_count == -1 {
-            let mut conn = pg_pool
-                .get()
-                .map_err(|err| error::handle_error(err.into()))?;
-            web::block(move || util::create_artifact_record(&mut conn, &transfer.map_space_id, &0))
-                .await?
-                .map_err(|err| error::handle_error(err.into()))?;
-            building_artifact_count = 0;
-        }
-
-        if transfer.artifacts_differ + building_artifact_count < 0 {
-            return Err(ErrorBadRequest("Not enough artifacts in the building"));
-        }
-
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        let building_capacity =
-            web::block(move || util::get_building_capacity(&mut conn, &transfer.map_space_id))
-                .await?
-                .map_err(|err| error::handle_error(err.into()))?;
-
-        if building_capacity < transfer.artifacts_differ + building_artifact_count {
-            return Err(ErrorBadRequest("Building capacity not sufficient"));
-        }
-
-        let new_building_artifact_count = building_artifact_count + transfer.artifacts_differ;
-        let new_bank_artifact_count = bank_artifact_count - transfer.artifacts_differ;
-
-        let mut conn = pg_pool
-            .get()
-            .map_err(|err| error::handle_error(err.into()))?;
-        web::block(move || {
-            util::transfer_artifacts_building(
-                &mut conn,
-                &transfer.map_space_id,
-                &bank_map_space_id,
-                &new_building_artifact_count,
-                &new_bank_artifact_count,
-            )
-        })
-        .await?
-        .map_err(|err| error::handle_error(err.into()))?;
-        accum_val += transfer.artifacts_differ;
-        responses.push(TransferArtifactResponse {
-            building_map_space_id: transfer.map_space_id,
-            artifacts_in_building: new_building_artifact_count,
+        moves.push((
+            transfer.map_space_id,
             bank_map_space_id,
-            artifacts_in_bank: new_bank_artifact_count,
-        });
+            transfer.artifacts_differ,
+        ));
     }
 
-    Ok(web::Json(responses))
+    let mut conn = pg_pool
+        .get()
+        .map_err(|err| error::handle_error(err.into()))?;
+    let batch_moves = moves.clone();
+    let new_counts = web::block(move || {
+        util::transfer_artifacts_batch(&mut conn, &user_id, &batch_moves, &request_id)
+    })
+    .await?
+    .map_err(error::handle_artifact_error)?;
+
+    let responses = moves
+        .into_iter()
+        .zip(new_counts)
+        .map(
+            |(
+                (building_map_space_id, bank_map_space_id, _),
+                (artifacts_in_building, artifacts_in_bank),
+            )| TransferArtifactResponse {
+                building_map_space_id,
+                artifacts_in_building,
+                bank_map_space_id,
+                artifacts_in_bank,
+            },
+        )
+        .collect();
+
+    Ok(responses)
 }
 
 async fn get_user_base_details(pool: Data<PgPool>, user: AuthUser) -> Result<impl Responder> {
@@ -437,7 +422,7 @@ async fn set_base_details(
     web::block(move || {
         let mut conn = pool.get()?;
         // util::set_map_invalid(&mut conn, map.id)?;
-        util::put_base_details(&map_spaces, &map, &mut conn)
+        util::put_base_details(&map_spaces, &map, &new_request_id(), &mut conn)
     })
     .await?
     .map_err(|err| error::handle_error(err.into()))?;
@@ -500,7 +485,7 @@ async fn confirm_base_details(
 
     web::block(move || {
         let mut conn = pool.get()?;
-        util::put_base_details(&map_spaces, &map, &mut conn)
+        util::put_base_details(&map_spaces, &map, &new_request_id(), &mut conn)
         // util::calculate_shortest_paths(&mut conn, map.id)?;
         // util::set_map_valid(&mut conn, map.id)
     })
@@ -30,9 +30,14 @@ pub fn run_shortest_paths(
         })?
         .to_vec();
 
+    Ok(shortest_paths_from_roads(&roads_list))
+}
+
+//shortest path next hops between every pair of connected road tiles
+pub fn shortest_paths_from_roads(roads_list: &[(i32, i32)]) -> HashMap<SourceDestXY, Coords> {
     let mut graph_2d = Array2D::filled_with(NO_BLOCK, MAP_SIZE, MAP_SIZE);
 
-    for road in &roads_list {
+    for road in roads_list {
         let (road_x, road_y) = (road.0, road.1);
         graph_2d
             .set(road_x as usize, road_y as usize, ROAD_ID)
@@ -41,7 +46,7 @@ pub fn run_shortest_paths(
 
     let mut adjacency_list: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
 
-    for road in &roads_list {
+    for road in roads_list {
         let (road_x, road_y) = (road.0, road.1);
         let mut neighbors = Vec::new();
 
@@ -62,7 +67,7 @@ pub fn run_shortest_paths(
 
     let mut shortest_paths: HashMap<SourceDestXY, Coords> = HashMap::new();
 
-    for (start_x, start_y) in &roads_list {
+    for (start_x, start_y) in roads_list {
         let start_node = (*start_x, *start_y);
         let mut visited: HashSet<(i32, i32)> = HashSet::new();
         let mut queue: VecDeque<((i32, i32), (i32, i32))> = VecDeque::new();
@@ -111,5 +116,5 @@ pub fn run_shortest_paths(
     //         })?;
     // }
 
-    Ok(shortest_paths)
+    shortest_paths
 }
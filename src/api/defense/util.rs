@@ -3,14 +3,19 @@ use super::MapSpacesEntry;
 use crate::api::auth::LoginResponse;
 use crate::api::error::AuthError;
 use crate::api::game::util::UserDetail;
+use crate::api::production::util::start_producers;
 use crate::api::user::util::fetch_user;
 use crate::api::util::GameHistoryEntry;
-use crate::api::util::{HistoryboardEntry, HistoryboardResponse};
+use crate::api::util::{
+    lock_artifacts, new_request_id, record_artifact_movements, run_artifact_transaction,
+    ArtifactMovement, HistoryboardEntry, HistoryboardResponse, LedgerReason, LockedArtifacts,
+};
 use crate::api::{self};
 use crate::constants::{BANK_BUILDING_NAME, INITIAL_ARTIFACTS, INITIAL_RATING, ROAD_ID};
 use crate::models::*;
 use crate::schema::prop;
 use crate::util::function;
+use crate::validator::util::default_loot_percentage;
 use crate::{api::util::GameHistoryResponse, error::DieselError};
 use anyhow::{Ok, Result};
 use diesel::dsl::exists;
@@ -19,7 +24,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct MapSpacesResponseWithArifacts {
     pub id: i32,
     pub x_coordinate: i32,
@@ -44,7 +49,7 @@ pub struct MineTypeResponseWithoutBlockId {
     pub name: String,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct MineTypeResponse {
     pub id: i32,
     pub radius: i32,
@@ -55,7 +60,7 @@ pub struct MineTypeResponse {
     pub name: String,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DefenderTypeResponse {
     pub id: i32,
     pub radius: i32,
@@ -67,7 +72,7 @@ pub struct DefenderTypeResponse {
     pub name: String,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct BuildingTypeResponse {
     pub id: i32,
     pub name: String,
@@ -80,6 +85,8 @@ pub struct BuildingTypeResponse {
     pub hp: i32,
     pub range: i32,
     pub frequency: i32,
+    #[serde(default = "default_loot_percentage")]
+    pub loot_percentage: i32,
 }
 
 #[derive(Serialize)]
@@ -99,7 +106,7 @@ pub struct AttackBaseResponse {
     pub mine_types: Vec<MineTypeResponseWithoutBlockId>,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulationBaseResponse {
     pub m: i32,                                 //map_id
     pub ms: Vec<MapSpacesResponseWithArifacts>, //map_spaces
@@ -162,72 +169,117 @@ pub fn defender_exists(defender: i32, conn: &mut PgConnection) -> Result<bool> {
         })?)
 }
 
+/// Moves `artifacts_differ` from the bank into a building, or back for a negative difference,
+/// with both buildings and the player locked. Answers the new counts of the building and bank.
 pub fn transfer_artifacts_building(
     conn: &mut PgConnection,
+    user_id: &i32,
     building_map_space_id: &i32,
     bank_map_space_id: &i32,
-    new_building_artifact_count: &i32,
-    new_bank_artifact_count: &i32,
-) -> Result<()> {
-    use crate::schema::artifact;
+    artifacts_differ: &i32,
+    request_id: &str,
+) -> Result<(i32, i32)> {
+    run_artifact_transaction(
+        conn,
+        &[*user_id],
+        &[*building_map_space_id, *bank_map_space_id],
+        |conn, locked| {
+            move_bank_artifacts(
+                conn,
+                locked,
+                *user_id,
+                *building_map_space_id,
+                *bank_map_space_id,
+                *artifacts_differ,
+                request_id,
+            )
+        },
+    )
+}
 
-    diesel::update(artifact::table.filter(artifact::map_space_id.eq(bank_map_space_id)))
-        .set(artifact::count.eq(new_bank_artifact_count))
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "map_spaces",
-            function: function!(),
-            error: err,
+/// Runs every `(building, bank, artifacts_differ)` transfer in order, as
+/// [`transfer_artifacts_building`] does, in one transaction locking every building involved.
+/// Either all of them go through or none does. Answers the new counts of each building and
+/// bank.
+pub fn transfer_artifacts_batch(
+    conn: &mut PgConnection,
+    user_id: &i32,
+    transfers: &[(i32, i32, i32)],
+    request_id: &str,
+) -> Result<Vec<(i32, i32)>> {
+    let map_space_ids: Vec<i32> = transfers
+        .iter()
+        .flat_map(|(building_map_space_id, bank_map_space_id, _)| {
+            [*building_map_space_id, *bank_map_space_id]
         })
-        .unwrap();
-
-    if *new_building_artifact_count == 0 {
-        diesel::delete(
-            artifact::dsl::artifact.filter(artifact::dsl::map_space_id.eq(building_map_space_id)),
-        )
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "shortest_path",
-            function: function!(),
-            error: err,
-        })?;
-        Ok(())
-    } else {
-        diesel::update(artifact::table.filter(artifact::map_space_id.eq(building_map_space_id)))
-            .set(artifact::count.eq(new_building_artifact_count))
-            .execute(conn)
-            .map_err(|err| DieselError {
-                table: "map_spaces",
-                function: function!(),
-                error: err,
-            })
-            .unwrap();
-        Ok(())
-    }
+        .collect();
+    run_artifact_transaction(conn, &[*user_id], &map_space_ids, |conn, locked| {
+        transfers
+            .iter()
+            .map(
+                |(building_map_space_id, bank_map_space_id, artifacts_differ)| {
+                    move_bank_artifacts(
+                        conn,
+                        locked,
+                        *user_id,
+                        *building_map_space_id,
+                        *bank_map_space_id,
+                        *artifacts_differ,
+                        request_id,
+                    )
+                },
+            )
+            .collect()
+    })
 }
 
-pub fn create_artifact_record(
+fn move_bank_artifacts(
     conn: &mut PgConnection,
-    map_space_id: &i32,
-    artifact_count: &i32,
-) -> Result<()> {
+    locked: &mut LockedArtifacts,
+    user_id: i32,
+    building_map_space_id: i32,
+    bank_map_space_id: i32,
+    artifacts_differ: i32,
+    request_id: &str,
+) -> Result<(i32, i32)> {
     use crate::schema::artifact;
 
-    let new_artifact = NewArtifact {
-        map_space_id: *map_space_id,
-        count: *artifact_count,
+    // A negative difference moves artifacts back into the bank
+    let (source, destination) = if artifacts_differ > 0 {
+        (bank_map_space_id, building_map_space_id)
+    } else {
+        (building_map_space_id, bank_map_space_id)
     };
+    let amount = artifacts_differ.abs();
+    locked.withdraw(conn, source, amount)?;
+    locked.deposit(conn, destination, amount)?;
 
-    diesel::insert_into(artifact::table)
-        .values(&new_artifact)
+    let new_building_artifact_count = locked.count(building_map_space_id);
+    if new_building_artifact_count == 0 {
+        diesel::delete(
+            artifact::dsl::artifact.filter(artifact::dsl::map_space_id.eq(building_map_space_id)),
+        )
         .execute(conn)
         .map_err(|err| DieselError {
             table: "artifact",
             function: function!(),
             error: err,
         })?;
+    }
 
-    Ok(())
+    record_artifact_movements(
+        &[ArtifactMovement {
+            user_id,
+            source: Some(source),
+            destination: Some(destination),
+            amount,
+            reason: LedgerReason::Transfer,
+            game_id: None,
+            request_id: Some(request_id.to_string()),
+        }],
+        conn,
+    )?;
+    Ok((new_building_artifact_count, locked.count(bank_map_space_id)))
 }
 
 pub fn get_block_id_of_bank(conn: &mut PgConnection, player: &i32) -> Result<i32> {
@@ -598,82 +650,125 @@ pub fn fetch_buildings(conn: &mut PgConnection) -> Result<Vec<BuildingType>> {
 pub fn put_base_details(
     maps: &[MapSpacesEntry],
     map: &MapLayout,
+    request_id: &str,
     conn: &mut PgConnection,
 ) -> Result<()> {
     use crate::schema::artifact;
     use crate::schema::map_spaces::dsl::*;
 
-    diesel::delete(artifact::table)
-        .filter(artifact::map_space_id.eq_any(map_spaces.filter(map_id.eq(map.id)).select(id)))
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "artifact",
-            function: function!(),
-            error: err,
-        })?;
+    conn.transaction(|conn| {
+        let old_map_space_ids: Vec<i32> = map_spaces
+            .filter(map_id.eq(map.id))
+            .select(id)
+            .load::<i32>(conn)
+            .map_err(|err| DieselError {
+                table: "map_spaces",
+                function: function!(),
+                error: err,
+            })?;
+        // Holds off transfers and attack payouts on this base until the new layout is in
+        let locked = lock_artifacts(conn, &[map.player], &old_map_space_ids)?;
+        let old_artifacts: Vec<(i32, i32)> = old_map_space_ids
+            .iter()
+            .map(|old_map_space_id| (*old_map_space_id, locked.count(*old_map_space_id)))
+            .filter(|(_, old_count)| *old_count != 0)
+            .collect();
 
-    diesel::delete(map_spaces)
-        .filter(map_id.eq(map.id))
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "map_spaces",
-            function: function!(),
-            error: err,
-        })?;
+        diesel::delete(artifact::table)
+            .filter(artifact::map_space_id.eq_any(map_spaces.filter(map_id.eq(map.id)).select(id)))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "artifact",
+                function: function!(),
+                error: err,
+            })?;
 
-    let m: Vec<NewMapSpaces> = maps
-        .iter()
-        .map(|e| NewMapSpaces {
-            map_id: map.id,
-            x_coordinate: e.x_coordinate,
-            y_coordinate: e.y_coordinate,
-            block_type_id: e.block_type_id,
-        })
-        .collect();
+        diesel::delete(map_spaces)
+            .filter(map_id.eq(map.id))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "map_spaces",
+                function: function!(),
+                error: err,
+            })?;
 
-    let result: Vec<MapSpaces> = diesel::insert_into(map_spaces)
-        .values(m)
-        .on_conflict_do_nothing()
-        .get_results(conn)
-        .map_err(|err| DieselError {
-            table: "map_spaces",
-            function: function!(),
-            error: err,
-        })?;
+        let m: Vec<NewMapSpaces> = maps
+            .iter()
+            .map(|e| NewMapSpaces {
+                map_id: map.id,
+                x_coordinate: e.x_coordinate,
+                y_coordinate: e.y_coordinate,
+                block_type_id: e.block_type_id,
+            })
+            .collect();
 
-    let mut map_space_map: HashMap<(i32, i32), i32> = HashMap::new();
-    for map_space in result {
-        map_space_map.insert(
-            (map_space.x_coordinate, map_space.y_coordinate),
-            map_space.id,
-        );
-    }
+        let result: Vec<MapSpaces> = diesel::insert_into(map_spaces)
+            .values(m)
+            .on_conflict_do_nothing()
+            .get_results(conn)
+            .map_err(|err| DieselError {
+                table: "map_spaces",
+                function: function!(),
+                error: err,
+            })?;
 
-    let artifact_entries: Vec<NewArtifact> = maps
-        .iter()
-        .filter_map(|e| {
-            if e.artifacts > 0 {
-                Some(NewArtifact {
-                    map_space_id: map_space_map[&(e.x_coordinate, e.y_coordinate)],
-                    count: e.artifacts,
-                })
-            } else {
-                None
-            }
-        })
-        .collect();
+        let mut map_space_map: HashMap<(i32, i32), i32> = HashMap::new();
+        for map_space in result {
+            map_space_map.insert(
+                (map_space.x_coordinate, map_space.y_coordinate),
+                map_space.id,
+            );
+        }
 
-    diesel::insert_into(artifact::table)
-        .values(artifact_entries)
-        .on_conflict_do_nothing()
-        .execute(conn)
-        .map_err(|err| DieselError {
-            table: "artifact",
-            function: function!(),
-            error: err,
-        })?;
+        start_producers(map.id, conn)?;
+
+        let artifact_entries: Vec<NewArtifact> = maps
+            .iter()
+            .filter_map(|e| {
+                if e.artifacts > 0 {
+                    Some(NewArtifact {
+                        map_space_id: map_space_map[&(e.x_coordinate, e.y_coordinate)],
+                        count: e.artifacts,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        diesel::insert_into(artifact::table)
+            .values(&artifact_entries)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "artifact",
+                function: function!(),
+                error: err,
+            })?;
 
-    Ok(())
+        // Every artifact is picked up from the old layout and put down in the new one
+        let taken = old_artifacts
+            .iter()
+            .map(|(old_map_space_id, old_count)| ArtifactMovement {
+                user_id: map.player,
+                source: Some(*old_map_space_id),
+                destination: None,
+                amount: *old_count,
+                reason: LedgerReason::Relayout,
+                game_id: None,
+                request_id: Some(request_id.to_string()),
+            });
+        let placed = artifact_entries.iter().map(|new| ArtifactMovement {
+            user_id: map.player,
+            source: None,
+            destination: Some(new.map_space_id),
+            amount: new.count,
+            reason: LedgerReason::Relayout,
+            game_id: None,
+            request_id: Some(request_id.to_string()),
+        });
+        record_artifact_movements(&taken.chain(placed).collect::<Vec<_>>(), conn)
+    })
 }
 
 pub fn get_level_constraints(
@@ -901,6 +996,7 @@ pub fn fetch_building_blocks(
                 hp: building_type.hp,
                 range: prop.range,
                 frequency: prop.frequency,
+                loot_percentage: building_type.loot_percentage,
             },
         )
         .collect();
@@ -1051,6 +1147,8 @@ pub fn add_user_default_base(
                 error: err,
             })?;
 
+        start_producers(map_layout.id, conn)?;
+
         let result: Vec<MapSpaces> = map_spaces::table
             .filter(map_spaces::map_id.eq(map_layout.id))
             .load::<MapSpaces>(conn)
@@ -1075,7 +1173,7 @@ pub fn add_user_default_base(
             .collect();
 
         diesel::insert_into(artifact::table)
-            .values(artifact_entries)
+            .values(&artifact_entries)
             .on_conflict_do_nothing()
             .execute(conn)
             .map_err(|err| DieselError {
@@ -1084,6 +1182,22 @@ pub fn add_user_default_base(
                 error: err,
             })?;
 
+        // Signing up sends no Idempotency-Key, so the grant gets an id of its own
+        let request_id = new_request_id();
+        let granted: Vec<ArtifactMovement> = artifact_entries
+            .iter()
+            .map(|entry| ArtifactMovement {
+                user_id: user.id,
+                source: None,
+                destination: Some(entry.map_space_id),
+                amount: entry.count,
+                reason: LedgerReason::Grant,
+                game_id: None,
+                request_id: Some(request_id.clone()),
+            })
+            .collect();
+        record_artifact_movements(&granted, conn)?;
+
         let new_available_blocks: Vec<NewAvailableBlocks> = available_blocks::table
             .filter(available_blocks::user_id.eq(bot_user_id))
             .load::<AvailableBlocks>(conn)
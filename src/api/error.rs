@@ -1,5 +1,7 @@
 use actix_web::{
-    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized},
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
     ResponseError,
 };
 use derive_more::Display;
@@ -10,6 +12,7 @@ use thiserror::Error;
 pub enum AuthError {
     Session,
     UserNotFound,
+    NotAdmin,
     Internal(Box<dyn std::error::Error + Send + Sync>),
 }
 
@@ -18,6 +21,7 @@ impl ResponseError for AuthError {
         match self {
             AuthError::Session => ErrorUnauthorized("Session Error. Please login again.").into(),
             AuthError::UserNotFound => ErrorNotFound("User Not Found").into(),
+            AuthError::NotAdmin => ErrorForbidden("Admin access required").into(),
             AuthError::Internal(err) => handle_error(err.to_string().into()).into(),
         }
     }
@@ -82,6 +86,31 @@ impl ResponseError for BaseInvalidError {
     }
 }
 
+/// A balance or capacity that no longer holds once the artifact rows are locked.
+#[derive(Debug, Display, Error)]
+pub enum ArtifactError {
+    #[display(fmt = "Not enough artifacts")]
+    NotEnoughArtifacts,
+    #[display(fmt = "Not enough artifacts in the building")]
+    NotEnoughInBuilding,
+    #[display(fmt = "Building capacity not sufficient")]
+    CapacityExceeded,
+}
+
+impl ResponseError for ArtifactError {
+    fn error_response(&self) -> actix_web::HttpResponse {
+        ErrorBadRequest(self.to_string()).into()
+    }
+}
+
+/// Answers an [`ArtifactError`] with a bad request and anything else as `handle_error` does.
+pub fn handle_artifact_error(err: anyhow::Error) -> actix_web::Error {
+    match err.downcast::<ArtifactError>() {
+        Ok(err) => err.into(),
+        Err(err) => handle_error(err.into()),
+    }
+}
+
 pub fn handle_error(err: Box<dyn std::error::Error>) -> actix_web::Error {
     log::error!("{}", err);
     ErrorInternalServerError("Internal Server Error")
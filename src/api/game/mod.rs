@@ -1,13 +1,19 @@
-use super::{auth::session::AuthUser, error, PgPool};
-use actix_web::{error::ErrorBadRequest, web, Responder, Result};
-use util::LeaderboardQuery;
+use super::{auth::session::AuthUser, error, PgPool, RedisPool};
+use actix_web::error::{ErrorBadRequest, ErrorServiceUnavailable};
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder, Result};
+use actix_ws::Message;
+use futures::channel::mpsc;
+use futures_util::future::{select, Either};
+use futures_util::stream::StreamExt;
+use util::{LeaderboardQuery, SpectatorSlots};
 
 pub mod util;
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/leaderboard").route(web::get().to(list_leaderboard)))
         .service(web::resource("/{game_id}/replay").route(web::get().to(get_replay)))
-        .service(web::resource("/{game_id}/stats").route(web::get().to(get_game_details)));
+        .service(web::resource("/{game_id}/stats").route(web::get().to(get_game_details)))
+        .service(web::resource("/{game_id}/spectate").route(web::get().to(spectate_game)));
 }
 
 async fn list_leaderboard(
@@ -71,3 +77,71 @@ async fn get_game_details(
 
     Ok(web::Json(response))
 }
+
+async fn spectate_game(
+    game_id: web::Path<i32>,
+    redis_pool: web::Data<RedisPool>,
+    spectator_slots: web::Data<SpectatorSlots>,
+    user: AuthUser,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let user_id = user.0;
+    let game_id = game_id.into_inner();
+
+    let mut redis_conn = redis_pool
+        .get()
+        .map_err(|err| error::handle_error(err.into()))?;
+    let attacker_id = util::fetch_attacker_of_live_game(game_id, &mut redis_conn)
+        .map_err(|err| error::handle_error(err.into()))?;
+    match attacker_id {
+        None => return Err(ErrorBadRequest("Game is not in progress")),
+        Some(attacker_id) if attacker_id == user_id => {
+            return Err(ErrorBadRequest("Attackers cannot spectate their own game"))
+        }
+        Some(_) => {}
+    }
+    let Some(slot) = SpectatorSlots::take(&spectator_slots) else {
+        return Err(ErrorServiceUnavailable(
+            "Too many spectators, try again later",
+        ));
+    };
+
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+
+    log::info!("User:{} is spectating game:{}", user_id, game_id);
+
+    // The subscription blocks for the whole game, so it gets a thread of its own rather than
+    // one from the blocking pool
+    let (sender, mut receiver) = mpsc::unbounded::<String>();
+    std::thread::spawn(move || {
+        let _slot = slot;
+        if let Err(err) = util::stream_game_events(game_id, redis_conn, sender) {
+            log::info!("Spectator feed of game:{} failed: {:?}", game_id, err);
+        }
+    });
+
+    actix_rt::spawn(async move {
+        loop {
+            match select(receiver.next(), msg_stream.next()).await {
+                Either::Left((Some(message), _)) => {
+                    if session.text(message).await.is_err() {
+                        return;
+                    }
+                }
+                Either::Right((Some(Ok(Message::Ping(bytes))), _)) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                // Spectators are read only
+                Either::Right((Some(Ok(Message::Text(_))), _)) => {}
+                _ => break,
+            }
+        }
+        let _ = session.close(None).await;
+        log::info!("User:{} stopped spectating game:{}", user_id, game_id);
+    });
+
+    Ok(response)
+}
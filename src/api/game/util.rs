@@ -1,11 +1,22 @@
+use crate::api::attack::socket::{ResultType, SpectatorMessage};
+use crate::api::attack::util::{SpectatorEvent, SpectatorState};
 use crate::api::util::can_show_replay;
+use crate::api::RedisConn;
+use crate::constants::{CLOCK_SYNC_INTERVAL_IN_SECONDS, LEGACY_REPLAY_LOG_VERSION, MAX_SPECTATORS};
 use crate::error::DieselError;
 use crate::models::{Game, LevelsFixture, MapLayout, SimulationLog};
-use crate::util::function;
-use anyhow::Result;
+use crate::util::{function, get_redis_client};
+use actix_web::web::Data;
+use anyhow::{bail, Result};
 use diesel::prelude::*;
 use diesel::{PgConnection, QueryDsl};
+use flate2::read::GzDecoder;
+use futures::channel::mpsc::UnboundedSender;
+use redis::Commands;
 use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 #[derive(Queryable, Deserialize, Serialize)]
 pub struct UserDetail {
@@ -15,6 +26,13 @@ pub struct UserDetail {
     pub avatar_id: i32,
 }
 
+#[derive(Serialize)]
+pub struct ReplayResponse {
+    pub game_id: i32,
+    pub version: i32,
+    pub log: serde_json::Value,
+}
+
 #[derive(Deserialize)]
 pub struct LeaderboardQuery {
     pub page: Option<i64>,
@@ -118,16 +136,40 @@ pub fn fetch_is_replay_allowed(
     Ok(false)
 }
 
-pub fn fetch_replay(game_id: i32, conn: &mut PgConnection) -> Result<SimulationLog> {
+pub fn fetch_replay(game_id: i32, conn: &mut PgConnection) -> Result<ReplayResponse> {
     use crate::schema::simulation_log;
-    Ok(simulation_log::table
+    let simulation_log: SimulationLog = simulation_log::table
         .filter(simulation_log::game_id.eq(game_id))
         .first(conn)
         .map_err(|err| DieselError {
             table: "simulation_log",
             function: function!(),
             error: err,
-        })?)
+        })?;
+
+    let log = decode_simulation_log(&simulation_log.log_text)?;
+    let version = log
+        .get("v")
+        .and_then(|version| version.as_i64())
+        .unwrap_or(LEGACY_REPLAY_LOG_VERSION as i64) as i32;
+
+    Ok(ReplayResponse {
+        game_id: simulation_log.game_id,
+        version,
+        log,
+    })
+}
+
+pub fn decode_simulation_log(log_text: &str) -> Result<serde_json::Value> {
+    // Logs from before replays were compressed are stored as plain JSON
+    if log_text.trim_start().starts_with('{') {
+        return Ok(serde_json::from_str(log_text)?);
+    }
+
+    let compressed_log = base64::decode(log_text)?;
+    let mut log_json = String::new();
+    GzDecoder::new(compressed_log.as_slice()).read_to_string(&mut log_json)?;
+    Ok(serde_json::from_str(&log_json)?)
 }
 
 pub fn fetch_game_details(game_id: i32, user_id: i32, conn: &mut PgConnection) -> Result<Game> {
@@ -143,3 +185,104 @@ pub fn fetch_game_details(game_id: i32, user_id: i32, conn: &mut PgConnection) -
             error: err,
         })?)
 }
+
+/// Returns the attacker of the game, or `None` if the game is not in progress.
+pub fn fetch_attacker_of_live_game(
+    game_id: i32,
+    redis_conn: &mut RedisConn,
+) -> Result<Option<i32>> {
+    let state_json: Option<String> = redis_conn
+        .get(format!("SpectateState:{}", game_id))
+        .map_err(|err| anyhow::anyhow!("Failed to get key: {}", err))?;
+    match state_json {
+        Some(state_json) => {
+            let SpectatorState { state, .. } = serde_json::from_str(&state_json)?;
+            Ok(Some(state.attacker_user_id))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Spectator feeds open on this server, capped at `MAX_SPECTATORS`.
+#[derive(Default)]
+pub struct SpectatorSlots(AtomicUsize);
+
+impl SpectatorSlots {
+    /// Takes a slot for a new feed, or returns `None` if all of them are taken.
+    pub fn take(slots: &Data<SpectatorSlots>) -> Option<SpectatorSlot> {
+        slots
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| {
+                (taken < MAX_SPECTATORS).then_some(taken + 1)
+            })
+            .ok()?;
+        Some(SpectatorSlot(slots.clone()))
+    }
+}
+
+/// A spectator feed's slot, given back when dropped.
+pub struct SpectatorSlot(Data<SpectatorSlots>);
+
+impl Drop for SpectatorSlot {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Forwards the spectator feed of a game to `sender`: the latest game state, then every event.
+///
+/// Blocks for as long as the game runs, so it has to be run off the async runtime. Returns once
+/// the game is over or `sender` is dropped.
+pub fn stream_game_events(
+    game_id: i32,
+    mut redis_conn: RedisConn,
+    sender: UnboundedSender<String>,
+) -> Result<()> {
+    let mut pubsub_conn = get_redis_client().get_connection()?;
+    let mut pubsub = pubsub_conn.as_pubsub();
+    pubsub.subscribe(format!("Spectate:{}", game_id))?;
+    pubsub.set_read_timeout(Some(Duration::from_secs(CLOCK_SYNC_INTERVAL_IN_SECONDS)))?;
+
+    // Read after subscribing so that no event falls between the state and the feed
+    let state_json: Option<String> = redis_conn.get(format!("SpectateState:{}", game_id))?;
+    let Some(state_json) = state_json else {
+        bail!("Game:{} is not in progress", game_id);
+    };
+    let SpectatorState { seq, state } = serde_json::from_str(&state_json)?;
+    // Only the subscription is needed from here on, so the pooled connection is given back
+    drop(redis_conn);
+    let message = serde_json::to_string(&SpectatorMessage::GameState(state))?;
+    if sender.unbounded_send(message).is_err() {
+        return Ok(());
+    }
+
+    loop {
+        let pubsub_message = match pubsub.get_message() {
+            Ok(pubsub_message) => pubsub_message,
+            Err(err) if err.is_timeout() => {
+                if sender.is_closed() {
+                    return Ok(());
+                }
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let payload: String = pubsub_message.get_payload()?;
+        let SpectatorEvent {
+            seq: event_seq,
+            event,
+        } = serde_json::from_str(&payload)?;
+
+        // Events up to `seq` are already part of the state; clock syncs and game over change
+        // nothing in it, so they are always passed on
+        let is_game_over = event.result_type == ResultType::GameOver;
+        if event_seq <= seq && !is_game_over && event.result_type != ResultType::ClockSync {
+            continue;
+        }
+
+        let message = serde_json::to_string(&SpectatorMessage::Event(Box::new(event)))?;
+        if sender.unbounded_send(message).is_err() || is_game_over {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::future::{ready, Future, Ready};
+
+use actix_web::{
+    dev::Payload,
+    error::{ErrorBadRequest, ErrorConflict, ErrorUnprocessableEntity},
+    http::StatusCode,
+    FromRequest, HttpRequest, HttpResponse, Result,
+};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+
+use super::{error, util::new_request_id, RedisConn, RedisPool};
+use crate::constants::{
+    IDEMPOTENCY_CLAIM_AGE_IN_SECONDS, IDEMPOTENCY_KEY_AGE_IN_SECONDS, IDEMPOTENCY_KEY_MAX_LENGTH,
+};
+
+/// The `Idempotency-Key` header of a request, if it was sent, with the method and path it was
+/// sent to.
+///
+/// Clients send the same key when they retry a request, so a retry after a timeout can be
+/// answered with the outcome of the first attempt instead of spending artifacts twice.
+pub struct IdempotencyKey {
+    key: Option<String>,
+    scope: String,
+    body_hash: u64,
+}
+
+impl IdempotencyKey {
+    /// Ties the key to the request body, so that reusing it with another body is refused.
+    pub fn for_body<T: Serialize>(self, body: &T) -> IdempotencyKey {
+        // Plain request structs always serialize
+        let body_json = serde_json::to_vec(body).unwrap_or_default();
+        IdempotencyKey {
+            body_hash: fnv1a_64(&body_json),
+            ..self
+        }
+    }
+
+    /// Names the request in the artifact ledger: the key when one was sent, a new id otherwise.
+    pub fn request_id(&self) -> String {
+        self.key.clone().unwrap_or_else(new_request_id)
+    }
+}
+
+impl FromRequest for IdempotencyKey {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let key = match req
+            .headers()
+            .get("Idempotency-Key")
+            .map(|value| value.to_str())
+        {
+            None => None,
+            Some(Ok(key)) if !key.is_empty() && key.len() <= IDEMPOTENCY_KEY_MAX_LENGTH => {
+                Some(key.to_string())
+            }
+            Some(_) => return ready(Err(ErrorBadRequest("Invalid Idempotency-Key header"))),
+        };
+        ready(Ok(IdempotencyKey {
+            key,
+            scope: format!("{}:{}", req.method(), req.path()),
+            body_hash: fnv1a_64(&[]),
+        }))
+    }
+}
+
+/// FNV-1a (64 bit), stable across builds as the hashes outlive the server that wrote them.
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum Outcome {
+    InProgress,
+    Done { status: u16, body: String },
+}
+
+/// What is kept under a key: the outcome, and the hash of the body it was first used with.
+#[derive(Serialize, Deserialize)]
+struct StoredOutcome {
+    body_hash: u64,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+/// Runs `handler` at most once per user, endpoint and idempotency key, answering its result as
+/// JSON.
+///
+/// The outcome is kept for `IDEMPOTENCY_KEY_AGE_IN_SECONDS`, and a request repeating a key gets
+/// the stored response back without `handler` running again. That covers client errors too, but
+/// not server errors, which leave the key free for another try. A repeat that arrives while the
+/// first request is still running is turned away with a conflict, and one with a different body
+/// as unprocessable. A key left in progress by a crash frees up after
+/// `IDEMPOTENCY_CLAIM_AGE_IN_SECONDS`.
+pub async fn run_idempotent<T, F, Fut>(
+    redis_pool: &RedisPool,
+    user_id: i32,
+    key: IdempotencyKey,
+    handler: F,
+) -> Result<HttpResponse>
+where
+    T: Serialize,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let IdempotencyKey {
+        key,
+        scope,
+        body_hash,
+    } = key;
+    let Some(key) = key else {
+        return handler().await.map(|body| HttpResponse::Ok().json(body));
+    };
+    let redis_key = format!("Idempotency:{}:{}:{}", user_id, scope, key);
+
+    let mut redis_conn = redis_pool
+        .get()
+        .map_err(|err| error::handle_error(err.into()))?;
+    let claimed = claim_key(&redis_key, body_hash, &mut redis_conn)
+        .map_err(|err| error::handle_error(err.into()))?;
+    match claimed {
+        None => {}
+        Some(stored) if stored.body_hash != body_hash => {
+            return Err(ErrorUnprocessableEntity(
+                "This Idempotency-Key was already used with a different request body",
+            ));
+        }
+        Some(StoredOutcome {
+            outcome: Outcome::Done { status, body },
+            ..
+        }) => {
+            let status =
+                StatusCode::from_u16(status).map_err(|err| error::handle_error(err.into()))?;
+            let mut response = HttpResponse::build(status);
+            if status.is_success() {
+                response.content_type("application/json");
+            }
+            return Ok(response.body(body));
+        }
+        Some(StoredOutcome {
+            outcome: Outcome::InProgress,
+            ..
+        }) => {
+            return Err(ErrorConflict(
+                "A request with this Idempotency-Key is still in progress",
+            ));
+        }
+    }
+
+    let result = handler().await;
+    let outcome = match &result {
+        Ok(body) => Outcome::Done {
+            status: StatusCode::OK.as_u16(),
+            body: serde_json::to_string(body).map_err(|err| error::handle_error(err.into()))?,
+        },
+        Err(err) if err.as_response_error().status_code().is_client_error() => Outcome::Done {
+            status: err.as_response_error().status_code().as_u16(),
+            body: err.to_string(),
+        },
+        Err(_) => {
+            if let Err(err) = redis_conn.del::<_, ()>(&redis_key) {
+                log::error!("Failed to release idempotency key {}: {}", redis_key, err);
+            }
+            return result.map(|body| HttpResponse::Ok().json(body));
+        }
+    };
+    // The request has gone through by now, so failing to store its outcome is only logged
+    let stored = StoredOutcome { body_hash, outcome };
+    if let Err(err) = store_outcome(&redis_key, &stored, &mut redis_conn) {
+        log::error!("Failed to store outcome of {}: {}", redis_key, err);
+    }
+    result.map(|body| HttpResponse::Ok().json(body))
+}
+
+/// Marks the key as in progress, or answers what is stored under it if it was already taken.
+///
+/// The claim only lasts `IDEMPOTENCY_CLAIM_AGE_IN_SECONDS`, and is kept for longer once the
+/// outcome is stored over it.
+fn claim_key(
+    redis_key: &str,
+    body_hash: u64,
+    redis_conn: &mut RedisConn,
+) -> anyhow::Result<Option<StoredOutcome>> {
+    let in_progress = StoredOutcome {
+        body_hash,
+        outcome: Outcome::InProgress,
+    };
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(redis_key)
+        .arg(serde_json::to_string(&in_progress)?)
+        .arg("NX")
+        .arg("EX")
+        .arg(IDEMPOTENCY_CLAIM_AGE_IN_SECONDS)
+        .query(&mut **redis_conn)
+        .map_err(|err| anyhow::anyhow!("Failed to claim idempotency key: {}", err))?;
+    if claimed.is_some() {
+        return Ok(None);
+    }
+
+    let stored: Option<String> = redis_conn
+        .get(redis_key)
+        .map_err(|err| anyhow::anyhow!("Failed to get idempotency key: {}", err))?;
+    match stored {
+        Some(stored) => Ok(Some(serde_json::from_str(&stored)?)),
+        // Expired in between, treated as a request still running so the client retries
+        None => Ok(Some(in_progress)),
+    }
+}
+
+fn store_outcome(
+    redis_key: &str,
+    outcome: &StoredOutcome,
+    redis_conn: &mut RedisConn,
+) -> anyhow::Result<()> {
+    redis_conn
+        .set_ex::<_, _, ()>(
+            redis_key,
+            serde_json::to_string(outcome)?,
+            IDEMPOTENCY_KEY_AGE_IN_SECONDS,
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to store idempotency key: {}", err))?;
+    Ok(())
+}
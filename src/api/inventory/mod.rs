@@ -1,6 +1,10 @@
 use self::util::{upgrade_attacker, upgrade_building, upgrade_defender, upgrade_emp, upgrade_mine};
 use super::{
-    attack::util::get_game_id_from_redis, auth::session::AuthUser, error, PgPool, RedisPool,
+    attack::util::get_game_id_from_redis,
+    auth::session::AuthUser,
+    error,
+    idempotency::{run_idempotent, IdempotencyKey},
+    PgPool, RedisPool,
 };
 use actix_web::{
     error::ErrorBadRequest,
@@ -37,9 +41,27 @@ async fn upgrade(
     pool: web::Data<PgPool>,
     redis_pool: web::Data<RedisPool>,
     user: AuthUser,
+    idempotency_key: IdempotencyKey,
     req: Json<UpgradeStruct>,
 ) -> Result<impl Responder> {
     let user_id = user.0;
+    let request_id = idempotency_key.request_id();
+    run_idempotent(
+        &redis_pool.clone(),
+        user_id,
+        idempotency_key.for_body(&*req),
+        || upgrade_item(pool, redis_pool, user_id, req, request_id),
+    )
+    .await
+}
+
+async fn upgrade_item(
+    pool: web::Data<PgPool>,
+    redis_pool: web::Data<RedisPool>,
+    user_id: i32,
+    req: Json<UpgradeStruct>,
+    request_id: String,
+) -> Result<i32> {
     let mut conn = pool.get().map_err(|err| error::handle_error(err.into()))?;
     let item_type = &req.item_type;
     let item_id = req.item_id;
@@ -55,19 +77,19 @@ async fn upgrade(
     let mut map_space_id_if_valid = 0;
 
     match item_type.as_str() {
-        "attacker" => upgrade_attacker(user_id, &mut conn, item_id)
+        "attacker" => upgrade_attacker(user_id, &mut conn, item_id, &request_id)
             .map_err(|err| ErrorBadRequest(err.to_string()))?,
         "building" => {
-            map_space_id_if_valid = upgrade_building(user_id, &mut conn, item_id)
+            map_space_id_if_valid = upgrade_building(user_id, &mut conn, item_id, &request_id)
                 .map_err(|err| ErrorBadRequest(err.to_string()))?;
         }
-        "defender" => upgrade_defender(user_id, &mut conn, item_id, true)
+        "defender" => upgrade_defender(user_id, &mut conn, item_id, true, &request_id)
             .map_err(|err| ErrorBadRequest(err.to_string()))?,
-        "emp" => upgrade_emp(user_id, &mut conn, item_id)
+        "emp" => upgrade_emp(user_id, &mut conn, item_id, &request_id)
             .map_err(|err| ErrorBadRequest(err.to_string()))?,
-        "mine" => upgrade_mine(user_id, &mut conn, item_id)
+        "mine" => upgrade_mine(user_id, &mut conn, item_id, &request_id)
             .map_err(|err| ErrorBadRequest(err.to_string()))?,
         _ => return Err(ErrorBadRequest("Invalid item type")),
     }
-    Ok(Json(map_space_id_if_valid))
+    Ok(map_space_id_if_valid)
 }
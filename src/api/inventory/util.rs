@@ -1,3 +1,4 @@
+use crate::api::util::{lock_artifacts, record_artifact_movements, ArtifactMovement, LedgerReason};
 use crate::constants::BANK_BUILDING_NAME;
 use crate::error::DieselError;
 use crate::models::{
@@ -228,6 +229,8 @@ fn get_building_types(
                             cost: 0,
                             hp: 0,
                             prop_id: 0,
+                            loot_percentage: 0,
+                            production_rate: 0,
                         },
                         BlockType {
                             id: 0,
@@ -657,6 +660,7 @@ pub(crate) fn upgrade_building(
     player_id: i32,
     conn: &mut PgConnection,
     block_id: i32,
+    request_id: &str,
 ) -> Result<i32> {
     let user_artifacts = get_user_artifacts(player_id, conn)?;
 
@@ -738,16 +742,16 @@ pub(crate) fn upgrade_building(
     if artifacts_in_bank < cost {
         return Err(anyhow::anyhow!("Not enough artifacts in bank"));
     }
-    let _ = run_transaction(
+    run_transaction(
         conn,
         block_id,
         next_level_block.0,
         player_id,
         cost,
-        user_artifacts,
         bank_map_space_id,
         true,
-    );
+        request_id,
+    )?;
 
     let building_map_space_id = get_building_map_space_id(conn, &id_of_map, &next_level_block.0)?;
     Ok(building_map_space_id)
@@ -758,6 +762,7 @@ pub(crate) fn upgrade_defender(
     conn: &mut PgConnection,
     block_id: i32,
     update_map_spaces: bool,
+    request_id: &str,
 ) -> Result<()> {
     let user_artifacts = get_user_artifacts(player_id, conn)?;
 
@@ -845,13 +850,18 @@ pub(crate) fn upgrade_defender(
         next_level_block_id,
         player_id,
         cost,
-        user_artifacts,
         bank_map_space_id,
         update_map_spaces,
+        request_id,
     )
 }
 
-pub(crate) fn upgrade_mine(player_id: i32, conn: &mut PgConnection, block_id: i32) -> Result<()> {
+pub(crate) fn upgrade_mine(
+    player_id: i32,
+    conn: &mut PgConnection,
+    block_id: i32,
+    request_id: &str,
+) -> Result<()> {
     let user_artifacts = get_user_artifacts(player_id, conn)?;
 
     //check if the given block id is a mine
@@ -934,9 +944,9 @@ pub(crate) fn upgrade_mine(player_id: i32, conn: &mut PgConnection, block_id: i3
         next_level_block_id,
         player_id,
         cost,
-        user_artifacts,
         bank_map_space_id,
         true,
+        request_id,
     )
 }
 
@@ -944,6 +954,7 @@ pub(crate) fn upgrade_attacker(
     player_id: i32,
     conn: &mut PgConnection,
     attacker_id: i32,
+    request_id: &str,
 ) -> Result<()> {
     let user_artifacts = get_user_artifacts(player_id, conn)?;
 
@@ -1011,6 +1022,8 @@ pub(crate) fn upgrade_attacker(
             error: err,
         })?;
     conn.transaction(|conn| {
+        pay_for_upgrade(conn, player_id, bank_map_space_id, cost, request_id)?;
+
         diesel::update(
             available_blocks::table
                 .filter(available_blocks::attacker_type_id.eq(attacker_id))
@@ -1019,25 +1032,16 @@ pub(crate) fn upgrade_attacker(
         .set(available_blocks::attacker_type_id.eq(next_level_attacker_id))
         .execute(conn)?;
 
-        //update artifacts in bank
-        diesel::update(artifact::table.filter(artifact::map_space_id.eq(bank_map_space_id)))
-            .set(artifact::count.eq(artifact::count - cost))
-            .execute(conn)
-            .map_err(|err| DieselError {
-                table: "artifact",
-                function: function!(),
-                error: err,
-            })?;
-
-        diesel::update(user::table.filter(user::id.eq(player_id)))
-            .set(user::artifacts.eq(user_artifacts - cost))
-            .execute(conn)?;
-
         Ok(())
     })
 }
 
-pub(crate) fn upgrade_emp(player_id: i32, conn: &mut PgConnection, emp_id: i32) -> Result<()> {
+pub(crate) fn upgrade_emp(
+    player_id: i32,
+    conn: &mut PgConnection,
+    emp_id: i32,
+    request_id: &str,
+) -> Result<()> {
     let user_artifacts = get_user_artifacts(player_id, conn)?;
 
     let joined_table = available_blocks::table
@@ -1098,6 +1102,8 @@ pub(crate) fn upgrade_emp(player_id: i32, conn: &mut PgConnection, emp_id: i32)
         return Err(anyhow::anyhow!("Not enough artifacts in bank"));
     }
     conn.transaction(|conn| {
+        pay_for_upgrade(conn, player_id, bank_map_space_id, cost, request_id)?;
+
         diesel::update(
             available_blocks::table
                 .filter(available_blocks::emp_type_id.eq(emp_id))
@@ -1106,36 +1112,23 @@ pub(crate) fn upgrade_emp(player_id: i32, conn: &mut PgConnection, emp_id: i32)
         .set(available_blocks::emp_type_id.eq(next_level_emp_id))
         .execute(conn)?;
 
-        //update artifacts in bank
-        diesel::update(artifact::table.filter(artifact::map_space_id.eq(bank_map_space_id)))
-            .set(artifact::count.eq(artifact::count - cost))
-            .execute(conn)
-            .map_err(|err| DieselError {
-                table: "artifact",
-                function: function!(),
-                error: err,
-            })?;
-
-        diesel::update(user::table.filter(user::id.eq(player_id)))
-            .set(user::artifacts.eq(user_artifacts - cost))
-            .execute(conn)?;
-
         Ok(())
     })
 }
 
-#[warn(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments)]
 fn run_transaction(
     conn: &mut PgConnection,
     block_id: i32,
     next_level_block_id: i32,
     player_id: i32,
     cost: i32,
-    user_artifacts: i32,
     bank_map_space_id: i32,
     update_map_spaces: bool,
+    request_id: &str,
 ) -> Result<(), anyhow::Error> {
     conn.transaction(|conn| {
+        pay_for_upgrade(conn, player_id, bank_map_space_id, cost, request_id)?;
         let id_of_map = get_user_map_id(player_id, conn)?;
 
         diesel::update(
@@ -1146,20 +1139,6 @@ fn run_transaction(
         .set(available_blocks::block_type_id.eq(next_level_block_id))
         .execute(conn)?;
 
-        diesel::update(user::table.filter(user::id.eq(player_id)))
-            .set(user::artifacts.eq(user_artifacts - cost))
-            .execute(conn)?;
-
-        //update artifacts in bank
-        diesel::update(artifact::table.filter(artifact::map_space_id.eq(bank_map_space_id)))
-            .set(artifact::count.eq(artifact::count - cost))
-            .execute(conn)
-            .map_err(|err| DieselError {
-                table: "artifact",
-                function: function!(),
-                error: err,
-            })?;
-
         //update map spaces
         if update_map_spaces {
             diesel::update(
@@ -1175,6 +1154,31 @@ fn run_transaction(
     })
 }
 
+/// Takes `cost` off the player's bank and `user.artifacts`, checked again with both locked.
+fn pay_for_upgrade(
+    conn: &mut PgConnection,
+    player_id: i32,
+    bank_map_space_id: i32,
+    cost: i32,
+    request_id: &str,
+) -> Result<()> {
+    let mut locked = lock_artifacts(conn, &[player_id], &[bank_map_space_id])?;
+    locked.spend(conn, player_id, cost)?;
+    locked.withdraw(conn, bank_map_space_id, cost)?;
+    record_artifact_movements(
+        &[ArtifactMovement {
+            user_id: player_id,
+            source: Some(bank_map_space_id),
+            destination: None,
+            amount: cost,
+            reason: LedgerReason::Upgrade,
+            game_id: None,
+            request_id: Some(request_id.to_string()),
+        }],
+        conn,
+    )
+}
+
 pub fn get_user_map_id(player_id: i32, conn: &mut PgConnection) -> Result<i32> {
     let id_of_map = map_layout::table
         .filter(map_layout::player.eq(player_id))
@@ -1,9 +1,12 @@
+pub mod admin;
 pub mod attack;
 pub mod auth;
 pub mod defense;
 pub mod error;
 pub mod game;
+pub mod idempotency;
 pub mod inventory;
+pub mod production;
 pub mod user;
 pub mod util;
 
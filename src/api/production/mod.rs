@@ -0,0 +1,53 @@
+use super::attack::util::get_game_id_from_redis;
+use super::auth::session::AuthUser;
+use super::{PgPool, RedisPool};
+use crate::api::error;
+use crate::api::util::new_request_id;
+use actix_web::error::ErrorBadRequest;
+use actix_web::web::{self, Data, Json};
+use actix_web::{Responder, Result};
+
+pub mod util;
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("").route(web::get().to(get_producers)))
+        .service(web::resource("/collect").route(web::post().to(collect_production)));
+}
+
+async fn get_producers(pool: Data<PgPool>, user: AuthUser) -> Result<impl Responder> {
+    let user_id = user.0;
+    let response = web::block(move || {
+        let mut conn = pool.get()?;
+        util::get_producers(user_id, &mut conn)
+    })
+    .await?
+    .map_err(|err| error::handle_error(err.into()))?;
+
+    Ok(Json(response))
+}
+
+async fn collect_production(
+    pool: Data<PgPool>,
+    redis_pool: Data<RedisPool>,
+    user: AuthUser,
+) -> Result<impl Responder> {
+    let user_id = user.0;
+    let mut redis_conn = redis_pool
+        .get()
+        .map_err(|err| error::handle_error(err.into()))?;
+
+    if let Ok(Some(_)) = get_game_id_from_redis(user_id, &mut redis_conn, false) {
+        return Err(ErrorBadRequest(
+            "You are under attack. Cannot collect artifacts",
+        ));
+    }
+
+    let response = web::block(move || {
+        let mut conn = pool.get()?;
+        util::collect_production(user_id, &new_request_id(), &mut conn)
+    })
+    .await?
+    .map_err(error::handle_artifact_error)?;
+
+    Ok(Json(response))
+}
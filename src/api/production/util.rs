@@ -0,0 +1,274 @@
+use crate::api::inventory::util::get_user_map_id;
+use crate::api::util::{lock_artifacts, record_artifact_movements, ArtifactMovement, LedgerReason};
+use crate::constants::PRODUCTION_PERIOD_IN_SECONDS;
+use crate::error::DieselError;
+use crate::schema::{artifact, artifact_production, block_type, building_type, map_spaces};
+use crate::util::function;
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Serialize;
+
+/// A producer on the player's base, with what it has made since it was last collected.
+#[derive(Serialize)]
+pub struct ProducerResponse {
+    pub map_space_id: i32,
+    pub production_rate: i32,
+    pub artifacts: i32,
+    pub capacity: i32,
+    pub accrued: i32,
+    pub last_collected_at: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct CollectResponse {
+    pub collected: i32,
+    pub producers: Vec<ProducerResponse>,
+}
+
+// (map space, production rate, capacity, artifacts held, last collected at)
+type Producer = (i32, i32, i32, Option<i32>, NaiveDateTime);
+
+pub fn get_producers(user_id: i32, conn: &mut PgConnection) -> Result<Vec<ProducerResponse>> {
+    let map_id = get_user_map_id(user_id, conn)?;
+    let now = get_db_now(conn)?;
+
+    let producers = load_producers(map_id, conn)?
+        .into_iter()
+        .map(
+            |(map_space_id, production_rate, capacity, artifacts, last_collected_at)| {
+                let artifacts = artifacts.unwrap_or(0);
+                ProducerResponse {
+                    map_space_id,
+                    production_rate,
+                    artifacts,
+                    capacity,
+                    accrued: accrued_artifacts(
+                        production_rate,
+                        last_collected_at,
+                        now,
+                        capacity - artifacts,
+                    ),
+                    last_collected_at,
+                }
+            },
+        )
+        .collect();
+    Ok(producers)
+}
+
+/// Moves what every producer on the player's base has made into the producer's own `artifact`
+/// row, where it counts towards `user.artifacts` and can be looted like any other artifacts.
+pub fn collect_production(
+    user_id: i32,
+    request_id: &str,
+    conn: &mut PgConnection,
+) -> Result<CollectResponse> {
+    use crate::schema::artifact_production::dsl::*;
+
+    let map_id = get_user_map_id(user_id, conn)?;
+    conn.transaction(|conn| {
+        let producer_ids: Vec<i32> = load_producers(map_id, conn)?
+            .iter()
+            .map(|producer| producer.0)
+            .collect();
+        let mut locked = lock_artifacts(conn, &[user_id], &producer_ids)?;
+
+        // Read again under the lock, so two collects cannot both be paid for the same time
+        let producers = load_producers(map_id, conn)?;
+        let now = get_db_now(conn)?;
+
+        let mut movements = Vec::new();
+        let mut responses = Vec::new();
+        for (producer_id, production_rate, capacity, _, collected_at) in producers {
+            let room = locked.room(producer_id);
+            let accrued = accrued_artifacts(production_rate, collected_at, now, room);
+            if accrued > 0 {
+                locked.deposit(conn, producer_id, accrued)?;
+                movements.push(ArtifactMovement {
+                    user_id,
+                    source: None,
+                    destination: Some(producer_id),
+                    amount: accrued,
+                    reason: LedgerReason::Production,
+                    game_id: None,
+                    request_id: Some(request_id.to_string()),
+                });
+            }
+
+            let new_collected_at =
+                collected_until(production_rate, collected_at, now, accrued, room);
+            diesel::update(artifact_production.find(producer_id))
+                .set(last_collected_at.eq(new_collected_at))
+                .execute(conn)
+                .map_err(|err| DieselError {
+                    table: "artifact_production",
+                    function: function!(),
+                    error: err,
+                })?;
+
+            responses.push(ProducerResponse {
+                map_space_id: producer_id,
+                production_rate,
+                artifacts: locked.count(producer_id),
+                capacity,
+                accrued: accrued_artifacts(
+                    production_rate,
+                    new_collected_at,
+                    now,
+                    locked.room(producer_id),
+                ),
+                last_collected_at: new_collected_at,
+            });
+        }
+
+        let collected: i32 = movements.iter().map(|movement| movement.amount).sum();
+        locked.earn(conn, user_id, collected)?;
+        record_artifact_movements(&movements, conn)?;
+
+        Ok(CollectResponse {
+            collected,
+            producers: responses,
+        })
+    })
+}
+
+/// Artifacts made between `since` and `now`, no more than the producer has room for.
+fn accrued_artifacts(
+    production_rate: i32,
+    since: NaiveDateTime,
+    now: NaiveDateTime,
+    room: i32,
+) -> i32 {
+    let produced =
+        (now - since).num_seconds().max(0) * production_rate as i64 / PRODUCTION_PERIOD_IN_SECONDS;
+    produced.min(room.max(0) as i64) as i32
+}
+
+/// Where the clock of a producer stands once `accrued` is collected: the time it took to make
+/// them, so that the part of an artifact made so far is carried over.
+fn collected_until(
+    production_rate: i32,
+    collected_at: NaiveDateTime,
+    now: NaiveDateTime,
+    accrued: i32,
+    room: i32,
+) -> NaiveDateTime {
+    // A full producer makes nothing, so the time it spent full is not carried over
+    if accrued >= room {
+        return now;
+    }
+    collected_at
+        + Duration::seconds(accrued as i64 * PRODUCTION_PERIOD_IN_SECONDS / production_rate as i64)
+}
+
+/// Starts the clock of producers on the base that have none yet, as the layout is saved. Their
+/// rows go with the map space when the layout is saved again, so a moved producer starts over.
+pub fn start_producers(map_id: i32, conn: &mut PgConnection) -> Result<()> {
+    let producer_ids: Vec<i32> = map_spaces::table
+        .inner_join(block_type::table.inner_join(building_type::table))
+        .filter(map_spaces::map_id.eq(map_id))
+        .filter(building_type::production_rate.gt(0))
+        .select(map_spaces::id)
+        .load::<i32>(conn)
+        .map_err(|err| DieselError {
+            table: "map_spaces",
+            function: function!(),
+            error: err,
+        })?;
+
+    let new_producers: Vec<_> = producer_ids
+        .into_iter()
+        .map(|producer_id| artifact_production::map_space_id.eq(producer_id))
+        .collect();
+    diesel::insert_into(artifact_production::table)
+        .values(&new_producers)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map_err(|err| DieselError {
+            table: "artifact_production",
+            function: function!(),
+            error: err,
+        })?;
+    Ok(())
+}
+
+fn load_producers(map_id: i32, conn: &mut PgConnection) -> Result<Vec<Producer>> {
+    let producers = map_spaces::table
+        .inner_join(block_type::table.inner_join(building_type::table))
+        .inner_join(artifact_production::table)
+        .left_join(artifact::table)
+        .filter(map_spaces::map_id.eq(map_id))
+        .filter(building_type::production_rate.gt(0))
+        .order_by(map_spaces::id)
+        .select((
+            map_spaces::id,
+            building_type::production_rate,
+            building_type::capacity,
+            artifact::count.nullable(),
+            artifact_production::last_collected_at,
+        ))
+        .load::<Producer>(conn)
+        .map_err(|err| DieselError {
+            table: "map_spaces",
+            function: function!(),
+            error: err,
+        })?;
+    Ok(producers)
+}
+
+// Production is timed by the database clock, the same one that fills `last_collected_at`
+fn get_db_now(conn: &mut PgConnection) -> Result<NaiveDateTime> {
+    let now = diesel::select(diesel::dsl::now)
+        .get_result::<NaiveDateTime>(conn)
+        .map_err(|err| DieselError {
+            table: "artifact_production",
+            function: function!(),
+            error: err,
+        })?;
+    Ok(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn accrues_at_the_production_rate() {
+        assert_eq!(accrued_artifacts(10, at(0), at(3600), 100), 10);
+        assert_eq!(accrued_artifacts(10, at(0), at(5400), 100), 15);
+        // Only whole artifacts are made
+        assert_eq!(accrued_artifacts(10, at(0), at(359), 100), 0);
+    }
+
+    #[test]
+    fn accrual_is_capped_by_room() {
+        assert_eq!(accrued_artifacts(10, at(0), at(36_000), 40), 40);
+        assert_eq!(accrued_artifacts(10, at(0), at(36_000), 0), 0);
+        assert_eq!(accrued_artifacts(10, at(0), at(36_000), -5), 0);
+    }
+
+    #[test]
+    fn nothing_accrues_before_the_clock_started() {
+        assert_eq!(accrued_artifacts(10, at(3600), at(0), 100), 0);
+    }
+
+    #[test]
+    fn part_of_an_artifact_is_carried_over() {
+        // 15 minutes make 2.5 artifacts at 10 an hour: 2 are collected, the half is kept
+        let collected_at = collected_until(10, at(0), at(900), 2, 100);
+        assert_eq!(collected_at, at(720));
+        assert_eq!(accrued_artifacts(10, collected_at, at(1080), 100), 1);
+    }
+
+    #[test]
+    fn time_spent_full_is_not_carried_over() {
+        assert_eq!(collected_until(10, at(0), at(36_000), 40, 40), at(36_000));
+        assert_eq!(collected_until(10, at(0), at(36_000), 0, 0), at(36_000));
+    }
+}
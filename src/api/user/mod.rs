@@ -1,6 +1,7 @@
 use super::auth::session::AuthUser;
 use super::{PgPool, RedisPool};
 use crate::api::error;
+use crate::api::util::{get_artifact_history, HistoryboardQuery};
 use crate::models::UpdateUser;
 use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorNotFound};
 use actix_web::web::{self, Data, Json, Path};
@@ -13,6 +14,7 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/update").route(web::patch().to(update_user)))
         .service(web::resource("/profile/{player_id}").route(web::get().to(view_user_profile)))
         .service(web::resource("/register").route(web::post().to(register)))
+        .service(web::resource("/artifacts/history").route(web::get().to(artifact_history)))
         .service(web::resource("/{id}/stats").route(web::get().to(get_user_stats)));
 }
 
@@ -139,3 +141,23 @@ async fn view_user_profile(player_id: Path<i32>, pool: Data<PgPool>) -> Result<i
         Err(ErrorNotFound("User not found"))
     }
 }
+
+async fn artifact_history(
+    pool: Data<PgPool>,
+    user: AuthUser,
+    query: web::Query<HistoryboardQuery>,
+) -> Result<impl Responder> {
+    let user_id = user.0;
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(20);
+    if page <= 0 || limit <= 0 {
+        return Err(ErrorBadRequest("Invalid query params"));
+    }
+    let response = web::block(move || {
+        let mut conn = pool.get()?;
+        get_artifact_history(user_id, page, limit, &mut conn)
+    })
+    .await?
+    .map_err(|err| error::handle_error(err.into()))?;
+    Ok(Json(response))
+}
@@ -1,11 +1,15 @@
+use crate::api::error::ArtifactError;
+use crate::constants::REQUEST_ID_LENGTH;
 use crate::error::DieselError;
-use crate::models::{Game, LevelsFixture};
+use crate::models::{ArtifactLedgerEntry, Game, LevelsFixture, NewArtifactLedgerEntry};
 use crate::util::function;
 use anyhow::Result;
 use chrono::Local;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use super::game::util::UserDetail;
 
@@ -46,6 +50,319 @@ pub struct GameHistoryEntry {
     pub is_replay_available: bool,
 }
 
+#[derive(Serialize)]
+pub struct ArtifactHistoryResponse {
+    pub entries: Vec<ArtifactLedgerEntry>,
+    pub last_page: i64,
+}
+
+/// Why artifacts moved, as stored in `artifact_ledger.reason`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LedgerReason {
+    // Taken from a defender's base by an attack
+    AttackLoot,
+    // Lost from a building damaged in an attack
+    AttackLoss,
+    // Moved between the bank and another building
+    Transfer,
+    // Spent on an upgrade
+    Upgrade,
+    // Given out, to a new player or by an admin bin
+    Grant,
+    // Moved when a player saves a new base layout
+    Relayout,
+    // Made by a producer building and collected into it
+    Production,
+    // `user.artifacts` corrected to match the base, only done by the bins
+    Adjustment,
+}
+
+impl LedgerReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerReason::AttackLoot => "attack_loot",
+            LedgerReason::AttackLoss => "attack_loss",
+            LedgerReason::Transfer => "transfer",
+            LedgerReason::Upgrade => "upgrade",
+            LedgerReason::Grant => "grant",
+            LedgerReason::Relayout => "relayout",
+            LedgerReason::Production => "production",
+            LedgerReason::Adjustment => "adjustment",
+        }
+    }
+}
+
+/// One movement of a player's artifacts.
+///
+/// `source` and `destination` are map spaces of the player's base, `None` on one side meaning
+/// the artifacts came in from or went out of the base. Adjustments only correct
+/// `user.artifacts`, so they have neither side and a signed `amount`, as does loot spilled to
+/// the balance without a building. Every movement carries the game or the request that made it.
+pub struct ArtifactMovement {
+    pub user_id: i32,
+    pub source: Option<i32>,
+    pub destination: Option<i32>,
+    pub amount: i32,
+    pub reason: LedgerReason,
+    pub game_id: Option<i32>,
+    pub request_id: Option<String>,
+}
+
+/// An id for the ledger rows of a request sent without an `Idempotency-Key`.
+pub fn new_request_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(REQUEST_ID_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Appends movements to `artifact_ledger`. Call it in the same transaction as the movements
+/// themselves so the ledger never disagrees with the counts.
+pub fn record_artifact_movements(
+    movements: &[ArtifactMovement],
+    conn: &mut PgConnection,
+) -> Result<()> {
+    use crate::schema::artifact_ledger;
+
+    let entries: Vec<NewArtifactLedgerEntry> = movements
+        .iter()
+        .filter(|movement| movement.amount != 0)
+        .map(|movement| NewArtifactLedgerEntry {
+            user_id: &movement.user_id,
+            source_map_space_id: movement.source.as_ref(),
+            destination_map_space_id: movement.destination.as_ref(),
+            amount: &movement.amount,
+            reason: movement.reason.as_str(),
+            game_id: movement.game_id.as_ref(),
+            request_id: movement.request_id.as_deref(),
+        })
+        .collect();
+
+    diesel::insert_into(artifact_ledger::table)
+        .values(&entries)
+        .execute(conn)
+        .map_err(|err| DieselError {
+            table: "artifact_ledger",
+            function: function!(),
+            error: err,
+        })?;
+    Ok(())
+}
+
+/// Artifact balances read under [`lock_artifacts`], written back through the same lock.
+pub struct LockedArtifacts {
+    user_artifacts: HashMap<i32, i32>,
+    counts: HashMap<i32, i32>,
+    capacities: HashMap<i32, i32>,
+}
+
+impl LockedArtifacts {
+    pub fn user_artifacts(&self, user_id: i32) -> i32 {
+        self.user_artifacts.get(&user_id).copied().unwrap_or(0)
+    }
+
+    // A building without an artifact row holds none
+    pub fn count(&self, map_space_id: i32) -> i32 {
+        self.counts.get(&map_space_id).copied().unwrap_or(0)
+    }
+
+    /// How many more artifacts the building can take.
+    pub fn room(&self, map_space_id: i32) -> i32 {
+        let capacity = self.capacities.get(&map_space_id).copied().unwrap_or(0);
+        (capacity - self.count(map_space_id)).max(0)
+    }
+
+    /// Takes `amount` out of a building, answering what is left in it.
+    pub fn withdraw(
+        &mut self,
+        conn: &mut PgConnection,
+        map_space_id: i32,
+        amount: i32,
+    ) -> Result<i32> {
+        if amount > self.count(map_space_id) {
+            return Err(ArtifactError::NotEnoughInBuilding.into());
+        }
+        self.set_count(conn, map_space_id, self.count(map_space_id) - amount)
+    }
+
+    /// Puts `amount` into a building, answering what it holds now.
+    pub fn deposit(
+        &mut self,
+        conn: &mut PgConnection,
+        map_space_id: i32,
+        amount: i32,
+    ) -> Result<i32> {
+        if amount > self.room(map_space_id) {
+            return Err(ArtifactError::CapacityExceeded.into());
+        }
+        self.set_count(conn, map_space_id, self.count(map_space_id) + amount)
+    }
+
+    /// Takes `amount` off `user.artifacts`, answering what is left.
+    pub fn spend(&mut self, conn: &mut PgConnection, user_id: i32, amount: i32) -> Result<i32> {
+        if amount > self.user_artifacts(user_id) {
+            return Err(ArtifactError::NotEnoughArtifacts.into());
+        }
+        self.set_user_artifacts(conn, user_id, self.user_artifacts(user_id) - amount)
+    }
+
+    /// Adds `amount` to `user.artifacts`, answering the new balance.
+    pub fn earn(&mut self, conn: &mut PgConnection, user_id: i32, amount: i32) -> Result<i32> {
+        self.set_user_artifacts(conn, user_id, self.user_artifacts(user_id) + amount)
+    }
+
+    fn set_user_artifacts(
+        &mut self,
+        conn: &mut PgConnection,
+        user_id: i32,
+        artifacts: i32,
+    ) -> Result<i32> {
+        use crate::schema::user;
+
+        diesel::update(user::table.find(user_id))
+            .set(user::artifacts.eq(artifacts))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "user",
+                function: function!(),
+                error: err,
+            })?;
+        self.user_artifacts.insert(user_id, artifacts);
+        Ok(artifacts)
+    }
+
+    fn set_count(&mut self, conn: &mut PgConnection, map_space_id: i32, count: i32) -> Result<i32> {
+        use crate::schema::artifact;
+
+        diesel::insert_into(artifact::table)
+            .values((
+                artifact::map_space_id.eq(map_space_id),
+                artifact::count.eq(count),
+            ))
+            .on_conflict(artifact::map_space_id)
+            .do_update()
+            .set(artifact::count.eq(count))
+            .execute(conn)
+            .map_err(|err| DieselError {
+                table: "artifact",
+                function: function!(),
+                error: err,
+            })?;
+        self.counts.insert(map_space_id, count);
+        Ok(count)
+    }
+}
+
+/// Locks the `user` rows of `user_ids` and the `artifact` rows of `map_space_ids` until the
+/// surrounding transaction ends, and reads their balances and capacities.
+///
+/// Users are locked before buildings, each in id order, so two movements touching the same rows
+/// wait on one another instead of deadlocking. Anything checked before the lock has to be
+/// checked again against what this answers.
+pub fn lock_artifacts(
+    conn: &mut PgConnection,
+    user_ids: &[i32],
+    map_space_ids: &[i32],
+) -> Result<LockedArtifacts> {
+    use crate::schema::{artifact, block_type, building_type, map_spaces, user};
+
+    let user_artifacts = user::table
+        .filter(user::id.eq_any(user_ids))
+        .order_by(user::id)
+        .select((user::id, user::artifacts))
+        .for_update()
+        .load::<(i32, i32)>(conn)
+        .map_err(|err| DieselError {
+            table: "user",
+            function: function!(),
+            error: err,
+        })?
+        .into_iter()
+        .collect();
+
+    let counts = artifact::table
+        .filter(artifact::map_space_id.eq_any(map_space_ids))
+        .order_by(artifact::map_space_id)
+        .select((artifact::map_space_id, artifact::count))
+        .for_update()
+        .load::<(i32, i32)>(conn)
+        .map_err(|err| DieselError {
+            table: "artifact",
+            function: function!(),
+            error: err,
+        })?
+        .into_iter()
+        .collect();
+
+    let capacities = map_spaces::table
+        .inner_join(block_type::table.inner_join(building_type::table))
+        .filter(map_spaces::id.eq_any(map_space_ids))
+        .select((map_spaces::id, building_type::capacity))
+        .load::<(i32, i32)>(conn)
+        .map_err(|err| DieselError {
+            table: "map_spaces",
+            function: function!(),
+            error: err,
+        })?
+        .into_iter()
+        .collect();
+
+    Ok(LockedArtifacts {
+        user_artifacts,
+        counts,
+        capacities,
+    })
+}
+
+/// Runs `f` in a transaction holding [`lock_artifacts`] on the given players and buildings.
+/// Every path that moves artifacts goes through here or locks the same way.
+pub fn run_artifact_transaction<T>(
+    conn: &mut PgConnection,
+    user_ids: &[i32],
+    map_space_ids: &[i32],
+    f: impl FnOnce(&mut PgConnection, &mut LockedArtifacts) -> Result<T>,
+) -> Result<T> {
+    conn.transaction(|conn| {
+        let mut locked = lock_artifacts(conn, user_ids, map_space_ids)?;
+        f(conn, &mut locked)
+    })
+}
+
+pub fn get_artifact_history(
+    user_id: i32,
+    page: i64,
+    limit: i64,
+    conn: &mut PgConnection,
+) -> Result<ArtifactHistoryResponse> {
+    use crate::schema::artifact_ledger;
+
+    let total_entries: i64 = artifact_ledger::table
+        .filter(artifact_ledger::user_id.eq(user_id))
+        .count()
+        .get_result(conn)
+        .map_err(|err| DieselError {
+            table: "artifact_ledger",
+            function: function!(),
+            error: err,
+        })?;
+    let last_page: i64 = (total_entries as f64 / limit as f64).ceil() as i64;
+
+    let entries = artifact_ledger::table
+        .filter(artifact_ledger::user_id.eq(user_id))
+        .order_by(artifact_ledger::id.desc())
+        .offset((page - 1) * limit)
+        .limit(limit)
+        .load::<ArtifactLedgerEntry>(conn)
+        .map_err(|err| DieselError {
+            table: "artifact_ledger",
+            function: function!(),
+            error: err,
+        })?;
+
+    Ok(ArtifactHistoryResponse { entries, last_page })
+}
+
 pub fn can_show_replay(requested_user: i32, game: &Game, levels_fixture: &LevelsFixture) -> bool {
     let current_date = Local::now().naive_local();
     requested_user == game.attack_id // user requesting history if an attacker or defender
@@ -1,7 +1,10 @@
 use anyhow::Ok;
+use aot_backend::api::util::{
+    record_artifact_movements, run_artifact_transaction, ArtifactMovement, LedgerReason,
+};
 use aot_backend::constants::BANK_BUILDING_NAME;
 use aot_backend::models::BlockCategory;
-use aot_backend::schema::{artifact, block_type, building_type, map_spaces, user};
+use aot_backend::schema::{block_type, building_type, map_layout, map_spaces};
 use aot_backend::util;
 use diesel::prelude::*;
 use diesel::QueryDsl;
@@ -12,31 +15,58 @@ fn main() {
     let pool = util::get_pg_conn_pool();
     let mut conn = pool.get().expect("Could not retrieve connection from pool");
 
-    // list of all bank's (level 1, 2, 3) space ids
-    let map_space_ids: Vec<i32> = map_spaces::table
+    // list of all bank's (level 1, 2, 3) space ids, with their owners
+    let banks: Vec<(i32, i32)> = map_spaces::table
         .inner_join(block_type::table.inner_join(building_type::table))
+        .inner_join(map_layout::table)
         .filter(block_type::category.eq(BlockCategory::Building))
         .filter(building_type::name.like(BANK_BUILDING_NAME))
-        .select(map_spaces::id)
-        .load::<i32>(&mut conn)
+        .select((map_spaces::id, map_layout::player))
+        .load::<(i32, i32)>(&mut conn)
         .expect("Could not get map space ids");
 
-    let _ = Ok(conn.transaction(|conn| {
-        for map_space_id in map_space_ids {
-            diesel::update(artifact::table.filter(artifact::map_space_id.eq(map_space_id)))
-                .set(artifact::count.eq(artifact::count + artifacts_to_increase_for_all_players))
-                .execute(conn)?;
-        }
-
-        diesel::update(user::table)
-            .set(user::artifacts.eq(user::artifacts + artifacts_to_increase_for_all_players))
-            .execute(conn)?;
-
-        Ok(())
-    }));
+    // Banks only take what fits, and players are only credited with what their bank took
+    let players: Vec<i32> = banks.iter().map(|(_, player)| *player).collect();
+    let bank_map_space_ids: Vec<i32> = banks
+        .iter()
+        .map(|(map_space_id, _)| *map_space_id)
+        .collect();
+    let request_id = format!(
+        "add_500_artifacts_to_users:{}",
+        chrono::Utc::now().timestamp()
+    );
+    let granted =
+        run_artifact_transaction(&mut conn, &players, &bank_map_space_ids, |conn, locked| {
+            let mut grants = Vec::new();
+            for (map_space_id, player) in banks.iter() {
+                let amount = locked
+                    .room(*map_space_id)
+                    .min(artifacts_to_increase_for_all_players);
+                if amount <= 0 {
+                    continue;
+                }
+                locked.deposit(conn, *map_space_id, amount)?;
+                locked.earn(conn, *player, amount)?;
+                grants.push(ArtifactMovement {
+                    user_id: *player,
+                    source: None,
+                    destination: Some(*map_space_id),
+                    amount,
+                    reason: LedgerReason::Grant,
+                    game_id: None,
+                    request_id: Some(request_id.clone()),
+                });
+            }
+            record_artifact_movements(&grants, conn)?;
+            Ok(grants.len())
+        })
+        .expect("Could not add artifacts");
 
     println!(
-        "Added {} artifacts to all users",
-        artifacts_to_increase_for_all_players
+        "Added up to {} artifacts to {} of {} banks, recorded as {}",
+        artifacts_to_increase_for_all_players,
+        granted,
+        banks.len(),
+        request_id
     );
 }
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Result};
+use aot_backend::api::util::{
+    lock_artifacts, record_artifact_movements, ArtifactMovement, LedgerReason,
+};
+use aot_backend::schema::{
+    artifact, artifact_ledger, block_type, building_type, map_layout, map_spaces, user,
+};
+use aot_backend::util;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::{env, io};
+
+#[derive(Default)]
+struct UserDrift {
+    user_artifacts: i32,
+    building_artifacts: i32,
+    pending_loot: i32,
+    // (map space, artifacts, capacity) of buildings holding more than they can, or less than 0
+    bad_buildings: Vec<(i32, i32, i32)>,
+}
+
+// Usage: reconcile_artifacts [--apply] [--yes]
+// Compares every player's `user.artifacts` with the artifacts in their buildings plus the loot
+// spilled to their balance since they last saved their base (as the ledger has it), and each
+// building's artifacts with its capacity, and prints what does not add up. Nothing is changed unless `--apply` is given, and
+// then only after confirming on stdin (or with `--yes`).
+//
+// Only `user.artifacts` is fixed. Each player is fixed in a transaction of their own, holding
+// the artifact lock on them and their buildings, and the drift is worked out again under the
+// lock so that games and transfers since the report are not undone. Each fix is recorded in the
+// artifact ledger as an adjustment under a request id naming this run. Buildings over capacity
+// or below zero are reported for a manual look.
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let apply = args.iter().any(|arg| arg == "--apply");
+    let confirmed = args.iter().any(|arg| arg == "--yes");
+
+    let pool = util::get_pg_conn_pool();
+    let mut conn = pool.get().expect("Could not retrieve connection from pool");
+
+    let mut drifts: BTreeMap<i32, UserDrift> = user::table
+        .select((user::id, user::artifacts))
+        .load::<(i32, i32)>(&mut conn)
+        .map_err(|err| anyhow!("Error getting users: {}", err))?
+        .into_iter()
+        .map(|(user_id, user_artifacts)| {
+            (
+                user_id,
+                UserDrift {
+                    user_artifacts,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+
+    let buildings: Vec<(i32, i32, i32, i32)> = artifact::table
+        .inner_join(
+            map_spaces::table
+                .inner_join(map_layout::table)
+                .inner_join(block_type::table.inner_join(building_type::table)),
+        )
+        .select((
+            map_layout::player,
+            artifact::map_space_id,
+            artifact::count,
+            building_type::capacity,
+        ))
+        .load::<(i32, i32, i32, i32)>(&mut conn)
+        .map_err(|err| anyhow!("Error getting building artifacts: {}", err))?;
+
+    for (user_id, pending_loot) in load_pending_loot(&mut conn, None)? {
+        drifts.entry(user_id).or_default().pending_loot = pending_loot;
+    }
+
+    for (player, map_space_id, count, capacity) in buildings {
+        let drift = drifts.entry(player).or_default();
+        drift.building_artifacts += count;
+        if count < 0 || count > capacity {
+            drift.bad_buildings.push((map_space_id, count, capacity));
+        }
+    }
+
+    let mut fixes = Vec::new();
+    for (user_id, drift) in drifts.iter() {
+        let difference = drift.building_artifacts + drift.pending_loot - drift.user_artifacts;
+        if difference != 0 {
+            println!(
+                "User {}: user.artifacts is {} but buildings hold {} and {} spilled loot is unplaced ({:+})",
+                user_id,
+                drift.user_artifacts,
+                drift.building_artifacts,
+                drift.pending_loot,
+                difference
+            );
+            fixes.push(*user_id);
+        }
+        for (map_space_id, count, capacity) in drift.bad_buildings.iter() {
+            println!(
+                "User {}: map space {} holds {} artifacts with a capacity of {}",
+                user_id, map_space_id, count, capacity
+            );
+        }
+    }
+
+    println!(
+        "{} users checked, {} with user.artifacts out of step",
+        drifts.len(),
+        fixes.len()
+    );
+
+    if !apply {
+        println!("Dry run, nothing changed. Run with --apply to fix user.artifacts");
+        return Ok(());
+    }
+    if fixes.is_empty() {
+        return Ok(());
+    }
+    if !confirmed {
+        println!("Set user.artifacts of {} users? [y/N]", fixes.len());
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Aborted, nothing changed");
+            return Ok(());
+        }
+    }
+
+    let request_id = format!("reconcile_artifacts:{}", chrono::Utc::now().timestamp());
+    let mut fixed = 0;
+    for user_id in fixes.iter() {
+        if reconcile_user(&mut conn, *user_id, &request_id)? {
+            fixed += 1;
+        }
+    }
+
+    println!(
+        "Fixed user.artifacts of {} users, recorded as {}",
+        fixed, request_id
+    );
+
+    Ok(())
+}
+
+/// Loot credited to `user.artifacts` without a building to hold it and not yet placed, per
+/// player.
+///
+/// A base save has to lay out every artifact of the player, so it places all loot spilled before
+/// it and leaves a relayout row in the ledger. Only the loot spilled after the player's last
+/// relayout is still pending. Both are written holding the artifact lock on the player, so the
+/// ledger ids are in the order they happened.
+fn load_pending_loot(conn: &mut PgConnection, user_id: Option<i32>) -> Result<BTreeMap<i32, i32>> {
+    let mut relayouts = artifact_ledger::table
+        .filter(artifact_ledger::reason.eq(LedgerReason::Relayout.as_str()))
+        .group_by(artifact_ledger::user_id)
+        .select((
+            artifact_ledger::user_id,
+            diesel::dsl::max(artifact_ledger::id),
+        ))
+        .into_boxed();
+    let mut spills = artifact_ledger::table
+        .filter(artifact_ledger::reason.eq(LedgerReason::AttackLoot.as_str()))
+        .filter(artifact_ledger::source_map_space_id.is_null())
+        .filter(artifact_ledger::destination_map_space_id.is_null())
+        .select((
+            artifact_ledger::user_id,
+            artifact_ledger::id,
+            artifact_ledger::amount,
+        ))
+        .into_boxed();
+    if let Some(user_id) = user_id {
+        relayouts = relayouts.filter(artifact_ledger::user_id.eq(user_id));
+        spills = spills.filter(artifact_ledger::user_id.eq(user_id));
+    }
+
+    let last_relayouts: HashMap<i32, i32> = relayouts
+        .load::<(i32, Option<i32>)>(conn)
+        .map_err(|err| anyhow!("Error getting relayouts: {}", err))?
+        .into_iter()
+        .filter_map(|(user_id, id)| Some((user_id, id?)))
+        .collect();
+
+    let mut pending_loot = BTreeMap::new();
+    for (user_id, id, amount) in spills
+        .load::<(i32, i32, i32)>(conn)
+        .map_err(|err| anyhow!("Error getting spilled loot: {}", err))?
+    {
+        let is_placed = last_relayouts
+            .get(&user_id)
+            .is_some_and(|last_relayout| id < *last_relayout);
+        if !is_placed {
+            *pending_loot.entry(user_id).or_insert(0) += amount;
+        }
+    }
+    Ok(pending_loot)
+}
+
+/// Sets `user.artifacts` of one player to what their buildings hold plus their pending loot, both
+/// read under the lock. Answers whether anything had to change.
+fn reconcile_user(conn: &mut PgConnection, user_id: i32, request_id: &str) -> Result<bool> {
+    conn.transaction(|conn| {
+        // Artifacts only move with their owner locked, so once the player is locked the set of
+        // their buildings holding artifacts cannot change
+        lock_artifacts(conn, &[user_id], &[])?;
+        let map_space_ids: Vec<i32> = artifact::table
+            .inner_join(map_spaces::table.inner_join(map_layout::table))
+            .filter(map_layout::player.eq(user_id))
+            .select(artifact::map_space_id)
+            .load::<i32>(conn)
+            .map_err(|err| anyhow!("Error getting building artifacts: {}", err))?;
+        let mut locked = lock_artifacts(conn, &[user_id], &map_space_ids)?;
+
+        let building_artifacts: i32 = map_space_ids.iter().map(|id| locked.count(*id)).sum();
+        let pending_loot = load_pending_loot(conn, Some(user_id))?
+            .get(&user_id)
+            .copied()
+            .unwrap_or(0);
+        let difference = building_artifacts + pending_loot - locked.user_artifacts(user_id);
+        if difference == 0 {
+            return Ok(false);
+        }
+
+        // A negative difference takes artifacts off, which `spend` would refuse past zero
+        locked.earn(conn, user_id, difference)?;
+        record_artifact_movements(
+            &[ArtifactMovement {
+                user_id,
+                source: None,
+                destination: None,
+                amount: difference,
+                reason: LedgerReason::Adjustment,
+                game_id: None,
+                request_id: Some(request_id.to_string()),
+            }],
+            conn,
+        )?;
+        Ok(true)
+    })
+}
@@ -0,0 +1,20 @@
+use aot_backend::validator::simulation::{simulate_attack, SimulationInput};
+use std::{env, fs, io};
+
+// Usage: resimulate_attack [input.json]
+// Reads `{ "base": <SimulationBaseResponse>, "requests": [<SocketRequest>, ...] }` from the
+// given file, or stdin if none, and prints the re-simulated result as JSON.
+fn main() {
+    let input = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(path).expect("Could not read input file"),
+        None => io::read_to_string(io::stdin()).expect("Could not read stdin"),
+    };
+    let input: SimulationInput = serde_json::from_str(&input).expect("Could not parse input");
+
+    let report = simulate_attack(input).expect("Could not simulate attack");
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("Could not serialize report")
+    );
+}
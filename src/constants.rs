@@ -11,8 +11,44 @@ pub const MAX_BOMBS_PER_ATTACK: i32 = 30;
 pub const ATTACK_TOKEN_AGE_IN_MINUTES: i64 = 5;
 pub const GAME_AGE_IN_MINUTES: usize = 3;
 pub const MATCH_MAKING_ATTEMPTS: i32 = 10;
-pub const PERCENTANGE_ARTIFACTS_OBTAINABLE: f32 = 0.3;
+// Share of a building's artifacts taken when it is destroyed, for bases saved before building
+// types had their own
+pub const DEFAULT_LOOT_PERCENTAGE: i32 = 30;
+// `building_type.production_rate` is the number of artifacts made in this many seconds
+pub const PRODUCTION_PERIOD_IN_SECONDS: i64 = 60 * 60;
 pub const BOMB_DAMAGE_MULTIPLIER: f32 = 5.0;
+pub const REPLAY_LOG_VERSION: i32 = 3;
+pub const LEGACY_REPLAY_LOG_VERSION: i32 = 1;
+// Length of one client simulation frame, used to express millisecond props in frames
+pub const FRAME_DURATION_IN_MILLIS: i32 = 100;
+pub const CLOCK_SYNC_INTERVAL_IN_SECONDS: u64 = 5;
+// Frames a client may run ahead of the server clock before they are rejected
+pub const MAX_FRAMES_AHEAD_OF_CLOCK: i32 = 10;
+// How long a dropped attack socket can be resumed before the game is settled
+pub const RECONNECT_GRACE_IN_SECONDS: u64 = 30;
+// How often unsettled games left behind by a crash are looked for
+pub const STALE_GAME_REAPER_INTERVAL_IN_SECONDS: u64 = 60;
+// How long live games get to finish on shutdown before they are saved for resumption, kept
+// under the 10s docker gives a container to stop
+pub const SHUTDOWN_DRAIN_IN_SECONDS: u64 = 5;
+// Frames between state checksums sent to the client
+pub const STATE_CHECKSUM_INTERVAL_IN_FRAMES: i32 = 10;
+// Spectator feeds one server keeps open at a time, each holding a thread and a Redis connection
+pub const MAX_SPECTATORS: usize = 100;
+// How long the outcome of a request sent with an Idempotency-Key is kept for replays
+pub const IDEMPOTENCY_KEY_AGE_IN_SECONDS: usize = 24 * 60 * 60;
+// How long a request sent with an Idempotency-Key holds the key while it runs, so that a key
+// left behind by a crash is soon free again
+pub const IDEMPOTENCY_CLAIM_AGE_IN_SECONDS: usize = 60;
+pub const IDEMPOTENCY_KEY_MAX_LENGTH: usize = 255;
+// Length of the id given to a request sent without an Idempotency-Key, to trace it in the ledger
+pub const REQUEST_ID_LENGTH: usize = 16;
+pub const SOCKET_PROTOCOL_VERSIONS: [i32; 2] = [1, 2];
+pub const LEGACY_SOCKET_PROTOCOL_VERSION: i32 = 1;
+pub const CLOCK_SYNC_CAPABILITY: &str = "clock_sync";
+pub const MSGPACK_CAPABILITY: &str = "msgpack";
+pub const SOCKET_CAPABILITIES: [&str; 2] = [CLOCK_SYNC_CAPABILITY, MSGPACK_CAPABILITY];
+pub const LEGACY_SOCKET_CAPABILITIES: [&str; 1] = [CLOCK_SYNC_CAPABILITY];
 
 pub struct HutLevelAttribute {
     pub defenders_limit: i32,
@@ -33,5 +69,3 @@ pub const LEVEL: [LevelAttributes; 3] = [
         hut: HutLevelAttribute { defenders_limit: 5 },
     },
 ];
-
-pub const LIVES: i32 = 3;
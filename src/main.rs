@@ -1,4 +1,4 @@
-use crate::api::{attack, auth, defense, game, inventory, user};
+use actix::Actor;
 use actix_cors::Cors;
 use actix_session::{
     config::PersistentSession, storage::RedisActorSessionStore, SessionMiddleware,
@@ -6,20 +6,15 @@ use actix_session::{
 use actix_web::cookie::time::Duration;
 use actix_web::web::Data;
 use actix_web::{cookie::Key, middleware, web, App, HttpResponse, HttpServer};
+use aot_backend::api::attack::reaper::GameReaper;
+use aot_backend::api::attack::registry::GameRegistry;
+use aot_backend::api::attack::shutdown::{self, ShutdownFlag};
+use aot_backend::api::game::util::SpectatorSlots;
+use aot_backend::api::{admin, attack, auth, defense, game, inventory, production, user};
+use aot_backend::util;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use flexi_logger::{Cleanup, Criterion, Duplicate, FileSpec, Naming};
 
-mod api;
-mod constants;
-mod error;
-mod models;
-mod schema;
-mod util;
-mod validator;
-
-#[macro_use]
-extern crate diesel;
-
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
 #[actix_web::main]
@@ -39,6 +34,15 @@ async fn main() -> std::io::Result<()> {
 
     let pg_pool = util::get_pg_conn_pool();
     let redis_pool = util::get_redis_conn_pool();
+    let game_registry = GameRegistry::default().start();
+    GameReaper::new(
+        Data::new(pg_pool.clone()),
+        Data::new(redis_pool.clone()),
+        game_registry.clone(),
+    )
+    .start();
+    let shutdown_flag = Data::new(ShutdownFlag::default());
+    let spectator_slots = Data::new(SpectatorSlots::default());
     let cookie_key = std::env::var("COOKIE_KEY").expect("COOKIE_KEY must be set");
     let key = Key::derive_from(cookie_key.as_bytes());
     let frontend_url = std::env::var("FRONTEND_URL").expect("FRONTEND_URL must be set");
@@ -50,7 +54,8 @@ async fn main() -> std::io::Result<()> {
         .expect("max age must be set!")
         .parse()
         .expect("max age must be an integer!");
-    HttpServer::new(move || {
+    let (signal_registry, signal_shutdown_flag) = (game_registry.clone(), shutdown_flag.clone());
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(
                 SessionMiddleware::builder(RedisActorSessionStore::new(&redis_url), key.clone())
@@ -74,7 +79,11 @@ async fn main() -> std::io::Result<()> {
             ))
             .app_data(Data::new(pg_pool.clone()))
             .app_data(Data::new(redis_pool.clone()))
+            .app_data(Data::new(game_registry.clone()))
+            .app_data(shutdown_flag.clone())
+            .app_data(spectator_slots.clone())
             .route("/", web::get().to(HttpResponse::Ok))
+            .service(web::scope("/admin").configure(admin::routes))
             .service(web::scope("/attack").configure(attack::routes))
             .service(
                 web::scope("/user")
@@ -84,8 +93,17 @@ async fn main() -> std::io::Result<()> {
             .service(web::scope("/base").configure(defense::routes))
             .service(web::scope("/game").configure(game::routes))
             .service(web::scope("/inventory").configure(inventory::routes))
+            .service(web::scope("/production").configure(production::routes))
     })
     .bind("0.0.0.0:8000")?
-    .run()
-    .await
+    // Signals are handled by `shut_down_on_signal` so live games can be drained first
+    .disable_signals()
+    .run();
+
+    actix_rt::spawn(shutdown::shut_down_on_signal(
+        server.handle(),
+        signal_registry,
+        signal_shutdown_flag,
+    ));
+    server.await
 }
@@ -18,7 +18,7 @@ pub enum ItemCategory {
     Block,
 }
 
-#[derive(Queryable, Serialize, Clone, Debug)]
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
 pub struct EmpType {
     pub id: i32,
     pub att_type: String,
@@ -75,6 +75,8 @@ pub struct BuildingType {
     pub cost: i32,
     pub hp: i32,
     pub prop_id: i32,
+    pub loot_percentage: i32,
+    pub production_rate: i32,
 }
 
 #[derive(Insertable)]
@@ -134,6 +136,7 @@ pub struct Game {
     pub is_game_over: bool,
     pub artifacts_collected: i32,
     pub date: NaiveDate,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Insertable)]
@@ -284,6 +287,40 @@ pub struct NewSimulationLog<'a> {
     pub log_text: &'a str,
 }
 
+#[derive(Insertable)]
+#[diesel(table_name = game_invalidation)]
+pub struct NewGameInvalidation<'a> {
+    pub game_id: &'a i32,
+    pub reason: &'a str,
+    pub frame_number: &'a i32,
+    pub policy: &'a str,
+}
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct ArtifactLedgerEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub source_map_space_id: Option<i32>,
+    pub destination_map_space_id: Option<i32>,
+    pub amount: i32,
+    pub reason: String,
+    pub game_id: Option<i32>,
+    pub request_id: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = artifact_ledger)]
+pub struct NewArtifactLedgerEntry<'a> {
+    pub user_id: &'a i32,
+    pub source_map_space_id: Option<&'a i32>, // None when the artifacts came from outside the base
+    pub destination_map_space_id: Option<&'a i32>, // None when they left the base
+    pub amount: &'a i32,
+    pub reason: &'a str,
+    pub game_id: Option<&'a i32>,
+    pub request_id: Option<&'a str>,
+}
+
 #[derive(AsChangeset, Debug, Deserialize)]
 #[diesel(table_name = user)]
 pub struct UpdateUser {
@@ -328,7 +365,7 @@ pub struct NewBlockType<'a> {
     pub category_id: &'a i32,
 }
 
-#[derive(Queryable, Clone, Debug, Serialize)]
+#[derive(Queryable, Clone, Debug, Serialize, Deserialize)]
 pub struct AttackerType {
     pub id: i32,
     pub max_health: i32,
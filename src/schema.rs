@@ -17,6 +17,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    artifact_ledger (id) {
+        id -> Int4,
+        user_id -> Int4,
+        source_map_space_id -> Nullable<Int4>,
+        destination_map_space_id -> Nullable<Int4>,
+        amount -> Int4,
+        reason -> Varchar,
+        game_id -> Nullable<Int4>,
+        request_id -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    artifact_production (map_space_id) {
+        map_space_id -> Int4,
+        last_collected_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     attack_type (id) {
         id -> Int4,
@@ -77,6 +98,8 @@ diesel::table! {
         cost -> Int4,
         hp -> Int4,
         prop_id -> Int4,
+        loot_percentage -> Int4,
+        production_rate -> Int4,
     }
 }
 
@@ -117,6 +140,17 @@ diesel::table! {
         is_game_over -> Bool,
         artifacts_collected -> Int4,
         date -> Date,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    game_invalidation (id) {
+        id -> Int4,
+        game_id -> Int4,
+        reason -> Text,
+        frame_number -> Int4,
+        policy -> Varchar,
     }
 }
 
@@ -212,6 +246,9 @@ diesel::table! {
 }
 
 diesel::joinable!(artifact -> map_spaces (map_space_id));
+diesel::joinable!(artifact_ledger -> game (game_id));
+diesel::joinable!(artifact_ledger -> user (user_id));
+diesel::joinable!(artifact_production -> map_spaces (map_space_id));
 diesel::joinable!(attacker_type -> prop (prop_id));
 diesel::joinable!(available_blocks -> attacker_type (attacker_type_id));
 diesel::joinable!(available_blocks -> block_type (block_type_id));
@@ -223,6 +260,7 @@ diesel::joinable!(block_type -> mine_type (mine_type));
 diesel::joinable!(building_type -> prop (prop_id));
 diesel::joinable!(defender_type -> prop (prop_id));
 diesel::joinable!(game -> map_layout (map_layout_id));
+diesel::joinable!(game_invalidation -> game (game_id));
 diesel::joinable!(level_constraints -> block_type (block_id));
 diesel::joinable!(level_constraints -> levels_fixture (level_id));
 diesel::joinable!(map_layout -> levels_fixture (level_id));
@@ -235,6 +273,8 @@ diesel::joinable!(simulation_log -> game (game_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     artifact,
+    artifact_ledger,
+    artifact_production,
     attack_type,
     attacker_type,
     available_blocks,
@@ -243,6 +283,7 @@ diesel::allow_tables_to_appear_in_same_query!(
     defender_type,
     emp_type,
     game,
+    game_invalidation,
     level_constraints,
     levels_fixture,
     map_layout,
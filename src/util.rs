@@ -11,13 +11,17 @@ pub fn get_pg_conn_pool() -> Pool<ConnectionManager<PgConnection>> {
         .expect("Failed to create pool.")
 }
 
-pub fn get_redis_conn_pool() -> Pool<redis::Client> {
+/// A client outside the pool, for connections that are held open such as pub/sub subscribers.
+pub fn get_redis_client() -> redis::Client {
     dotenv::dotenv().ok();
     let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
-    let manager =
-        redis::Client::open(format!("redis://{redis_url}")).expect("Failed to create redis client");
+    redis::Client::open(format!("redis://{redis_url}")).expect("Failed to create redis client")
+}
+
+pub fn get_redis_conn_pool() -> Pool<redis::Client> {
+    dotenv::dotenv().ok();
     Pool::builder()
-        .build(manager)
+        .build(get_redis_client())
         .expect("Failed to create pool.")
 }
 
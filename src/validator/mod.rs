@@ -2,9 +2,12 @@ use std::collections::{HashMap, HashSet};
 
 use crate::{
     api::attack::{
-        socket::{ActionType, BuildingResponse, ResultType, SocketRequest, SocketResponse},
-        util::{Direction, EventResponse, GameLog},
+        socket::{
+            ActionType, BatchRequest, BuildingResponse, ResultType, SocketRequest, SocketResponse,
+        },
+        util::{GameEvent, GameLog},
     },
+    constants::STATE_CHECKSUM_INTERVAL_IN_FRAMES,
     models::AttackerType,
     validator::util::{Coords, SourceDestXY},
 };
@@ -16,6 +19,7 @@ use self::{
 };
 
 pub mod error;
+pub mod simulation;
 pub mod state;
 pub mod util;
 
@@ -26,35 +30,207 @@ pub fn game_handler(
     _shortest_path: &HashMap<SourceDestXY, Coords>,
     _roads: &HashSet<(i32, i32)>,
     _bomb_types: &Vec<BombType>,
-    mut _game_log: &mut GameLog,
+    _game_log: &mut GameLog,
+) -> Option<Result<SocketResponse>> {
+    let request = socket_request.clone();
+    let is_desynced = socket_request
+        .state_checksum
+        .is_some_and(|checksum| _game_state.is_desynced(&checksum));
+    if is_desynced {
+        log::info!(
+            "Attacker:{} is out of sync at frame {}",
+            _game_state.attacker_user_id,
+            socket_request.frame_number
+        );
+    }
+
+    let mut response_result = handle_action(
+        attacker_type,
+        socket_request,
+        _game_state,
+        _shortest_path,
+        _roads,
+        _bomb_types,
+        _game_log,
+    );
+
+    if let Some(response) = response_result
+        .as_mut()
+        .and_then(|result| result.as_mut().ok())
+    {
+        if request.frame_number % STATE_CHECKSUM_INTERVAL_IN_FRAMES == 0 {
+            response.state_checksum = Some(_game_state.record_checksum(request.frame_number));
+            response.is_sync = true;
+        }
+        // Resync with the authoritative state, which the client adopts as is
+        if is_desynced {
+            response.state_checksum = Some(_game_state.record_checksum(request.frame_number));
+            response.state = Some(_game_state.attacker_state_response());
+            response.is_sync = true;
+        }
+
+        _game_log.e.push(GameEvent {
+            f: request.frame_number,
+            q: request,
+            s: response.clone(),
+        });
+    }
+
+    response_result
+}
+
+/// Applies a batch of actions in order, all or nothing: if any action fails, `State` and the game
+/// log are put back as they were before the batch.
+///
+/// The responses are combined into one, with the mines, defenders and buildings of every action.
+/// Actions after one that ends the game are dropped.
+pub fn batch_handler(
+    attacker_type: &HashMap<i32, AttackerType>,
+    batch_request: BatchRequest,
+    _game_state: &mut State,
+    _shortest_path: &HashMap<SourceDestXY, Coords>,
+    _roads: &HashSet<(i32, i32)>,
+    _bomb_types: &Vec<BombType>,
+    _game_log: &mut GameLog,
+) -> Option<Result<SocketResponse>> {
+    let Some(first_action) = batch_request.actions.first() else {
+        return Some(Err(anyhow::anyhow!("Empty batch")));
+    };
+    let is_in_order = batch_request
+        .actions
+        .windows(2)
+        .all(|actions| actions[0].frame_number <= actions[1].frame_number);
+    if !is_in_order {
+        return Some(Err(anyhow::anyhow!("Batch actions are out of frame order")));
+    }
+
+    let mut batch_response = SocketResponse {
+        frame_number: first_action.frame_number,
+        result_type: ResultType::Batch,
+        attacker_instance_id: None,
+        is_alive: None,
+        attacker_health: None,
+        exploded_mines: Some(Vec::new()),
+        defender_damaged: Some(Vec::new()),
+        damaged_buildings: Some(Vec::new()),
+        hut_triggered: false,
+        hut_defenders: Some(Vec::new()),
+        total_damage_percentage: Some(_game_state.damage_percentage),
+        is_sync: false,
+        remaining_time_in_millis: None,
+        error_code: None,
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: Some(String::from("Batch Response")),
+        result: None,
+    };
+
+    let state_before_batch = _game_state.clone();
+    let events_before_batch = _game_log.e.len();
+    let result_before_batch = _game_log.r.clone();
+
+    for action in batch_request.actions {
+        let response = match game_handler(
+            attacker_type,
+            action,
+            _game_state,
+            _shortest_path,
+            _roads,
+            _bomb_types,
+            _game_log,
+        ) {
+            Some(Err(err)) => {
+                // Flags raised along the way are kept for the audit trail
+                let invalidations = std::mem::take(&mut _game_state.invalidations);
+                let in_validation = _game_state.in_validation.clone();
+                *_game_state = state_before_batch;
+                _game_state.invalidations = invalidations;
+                _game_state.in_validation = in_validation;
+                _game_log.e.truncate(events_before_batch);
+                _game_log.r = result_before_batch;
+                return Some(Err(err));
+            }
+            Some(Result::Ok(response)) => response,
+            None => continue,
+        };
+
+        batch_response.frame_number = response.frame_number;
+        batch_response.attacker_instance_id = response
+            .attacker_instance_id
+            .or(batch_response.attacker_instance_id);
+        batch_response.is_alive = response.is_alive.or(batch_response.is_alive);
+        batch_response.attacker_health =
+            response.attacker_health.or(batch_response.attacker_health);
+        batch_response
+            .exploded_mines
+            .get_or_insert_with(Vec::new)
+            .extend(response.exploded_mines.unwrap_or_default());
+        batch_response
+            .defender_damaged
+            .get_or_insert_with(Vec::new)
+            .extend(response.defender_damaged.unwrap_or_default());
+        batch_response
+            .damaged_buildings
+            .get_or_insert_with(Vec::new)
+            .extend(response.damaged_buildings.unwrap_or_default());
+        batch_response
+            .hut_defenders
+            .get_or_insert_with(Vec::new)
+            .extend(response.hut_defenders.unwrap_or_default());
+        batch_response.hut_triggered |= response.hut_triggered;
+        batch_response.total_damage_percentage = response
+            .total_damage_percentage
+            .or(batch_response.total_damage_percentage);
+        batch_response.is_sync |= response.is_sync;
+        batch_response.state_checksum = response.state_checksum.or(batch_response.state_checksum);
+        batch_response.state = response.state.or(batch_response.state);
+
+        if response.result_type == ResultType::GameOver {
+            batch_response.result_type = ResultType::GameOver;
+            batch_response.is_game_over = true;
+            batch_response.message = response.message;
+            break;
+        }
+    }
+
+    Some(Ok(batch_response))
+}
+
+fn handle_action(
+    attacker_type: &HashMap<i32, AttackerType>,
+    socket_request: SocketRequest,
+    _game_state: &mut State,
+    _shortest_path: &HashMap<SourceDestXY, Coords>,
+    _roads: &HashSet<(i32, i32)>,
+    _bomb_types: &Vec<BombType>,
+    _game_log: &mut GameLog,
 ) -> Option<Result<SocketResponse>> {
     let defender_damaged_result: DefenderReturnType;
     let exploded_mines_result: Vec<MineDetails>;
     let buildings_damaged_result: Vec<BuildingResponse>;
+    // Clients that predate multiple attackers only ever deploy one, as instance 0
+    let instance_id = socket_request.attacker_instance_id.unwrap_or(0);
     match socket_request.action_type {
         ActionType::PlaceAttacker => {
             _game_state.update_frame_number(socket_request.frame_number);
 
-            let mut event_response = EventResponse {
-                attacker_id: None,
-                bomb_id: None,
-                coords: Coords { x: 0, y: 0 },
-                direction: Direction::Up,
-                is_bomb: false,
-            };
-
             if let Some(attacker_id) = socket_request.attacker_id {
                 let attacker: AttackerType = attacker_type.get(&attacker_id).unwrap().clone();
-                _game_state.place_attacker(Attacker {
-                    id: attacker.id,
-                    path_in_current_frame: Vec::new(),
-                    attacker_pos: socket_request.start_position.unwrap(),
-                    attacker_health: attacker.max_health,
-                    attacker_speed: attacker.speed,
-                    bombs: Vec::new(),
-                    trigger_defender: false,
-                    bomb_count: attacker.amt_of_emps,
-                });
+                _game_state.place_attacker(
+                    socket_request.frame_number,
+                    instance_id,
+                    Attacker {
+                        id: attacker.id,
+                        path_in_current_frame: Vec::new(),
+                        attacker_pos: socket_request.start_position.unwrap(),
+                        attacker_health: attacker.max_health,
+                        attacker_speed: attacker.speed,
+                        bombs: Vec::new(),
+                        trigger_defender: false,
+                        bomb_count: attacker.amt_of_emps,
+                    },
+                );
 
                 for bomb_type in _bomb_types {
                     if let Some(bomb_id) = socket_request.bomb_id {
@@ -63,15 +239,9 @@ pub fn game_handler(
                         }
                     }
                 }
-
-                event_response.attacker_id = Some(attacker_id);
-                event_response.coords = socket_request.start_position.unwrap();
             }
 
             // _game_state.set_mines(mine_positions);
-            event_response.bomb_id = socket_request.bomb_id;
-
-            _game_log.e.push(event_response);
             _game_log.r.au += 1;
 
             if _game_state.in_validation.is_invalidated {
@@ -84,6 +254,7 @@ pub fn game_handler(
             return Some(Ok(SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type: ResultType::PlacedAttacker,
+                attacker_instance_id: Some(instance_id),
                 is_alive: Some(true),
 
                 attacker_health: None,
@@ -95,13 +266,24 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: false,
                 message: Some(String::from(
                     "Place Attacker, set attacker and bomb response",
                 )),
+                result: None,
             }));
         }
         ActionType::MoveAttacker => {
+            if !_game_state.attackers.contains_key(&instance_id) {
+                return Some(Err(anyhow::anyhow!(
+                    "Attacker instance {} is not deployed",
+                    instance_id
+                )));
+            }
             if let Some(attacker_id) = socket_request.attacker_id {
                 let attacker: AttackerType = attacker_type.get(&attacker_id).unwrap().clone();
                 let attacker_delta: Vec<Coords> = socket_request.attacker_path;
@@ -110,6 +292,7 @@ pub fn game_handler(
                 let attacker_result = _game_state.attacker_movement(
                     socket_request.frame_number,
                     _roads,
+                    instance_id,
                     Attacker {
                         id: attacker.id,
                         path_in_current_frame: attacker_delta.clone(),
@@ -124,33 +307,11 @@ pub fn game_handler(
 
                 let attacker_result_clone = attacker_result.clone().unwrap();
 
-                defender_damaged_result =
-                    _game_state.defender_movement(attacker_delta.clone(), _shortest_path);
-
-                for coord in attacker_delta {
-                    let mut direction = Direction::Up;
-
-                    let prev_pos = _game_log.e.last().unwrap().coords;
-                    if prev_pos.x < coord.x {
-                        direction = Direction::Down;
-                    } else if prev_pos.x > coord.x {
-                        direction = Direction::Up;
-                    } else if prev_pos.y < coord.y {
-                        direction = Direction::Left;
-                    } else if prev_pos.y > coord.y {
-                        direction = Direction::Right;
-                    }
-
-                    let event_response = EventResponse {
-                        attacker_id: None,
-                        bomb_id: None,
-                        coords: coord,
-                        direction,
-                        is_bomb: false,
-                    };
-
-                    _game_log.e.push(event_response.clone());
-                }
+                defender_damaged_result = _game_state.defender_movement(
+                    instance_id,
+                    attacker_delta.clone(),
+                    _shortest_path,
+                );
 
                 // let mut bool_temp = false;
                 // if attacker_result_clone.trigger_defender {
@@ -162,13 +323,7 @@ pub fn game_handler(
                 //     ResultType::Nothing
                 // };
 
-                let mut is_attacker_alive = true;
-
-                if let Some(attacker) = &_game_state.attacker {
-                    if attacker.attacker_health == 0 {
-                        is_attacker_alive = false;
-                    }
-                }
+                let is_attacker_alive = _game_state.is_attacker_alive(instance_id);
 
                 if _game_state.in_validation.is_invalidated {
                     return Some(Ok(send_terminate_game_message(
@@ -180,6 +335,7 @@ pub fn game_handler(
                 let spawn_result = _game_state
                     .spawn_hut_defender(
                         _roads,
+                        instance_id,
                         Attacker {
                             id: attacker.id,
                             path_in_current_frame: attacker_delta_clone.clone(),
@@ -206,6 +362,7 @@ pub fn game_handler(
                 let response = SocketResponse {
                     frame_number: socket_request.frame_number,
                     result_type,
+                    attacker_instance_id: Some(instance_id),
                     is_alive: Some(is_attacker_alive),
                     attacker_health: Some(defender_damaged_result.clone().attacker_health),
                     exploded_mines: None,
@@ -216,8 +373,13 @@ pub fn game_handler(
                     hut_defenders: Some(spawn_result),
                     total_damage_percentage: Some(_game_state.damage_percentage),
                     is_sync: false,
+                    remaining_time_in_millis: None,
+                    error_code: None,
+                    state_checksum: None,
+                    state: None,
                     is_game_over: false,
                     message: Some(String::from("Movement Response")),
+                    result: None,
                 };
                 return Some(Ok(response));
             }
@@ -225,7 +387,7 @@ pub fn game_handler(
         ActionType::IsMine => {
             // is_mine
             let start_pos: Option<Coords> = socket_request.start_position;
-            exploded_mines_result = _game_state.mine_blast(start_pos);
+            exploded_mines_result = _game_state.mine_blast(instance_id, start_pos);
 
             let mut bool_temp = false;
             if !exploded_mines_result.is_empty() {
@@ -237,13 +399,7 @@ pub fn game_handler(
                 ResultType::Nothing
             };
 
-            let mut is_attacker_alive = true;
-
-            if let Some(attacker) = &_game_state.attacker {
-                if attacker.attacker_health == 0 {
-                    is_attacker_alive = false;
-                }
-            }
+            let is_attacker_alive = _game_state.is_attacker_alive(instance_id);
 
             if _game_state.in_validation.is_invalidated {
                 return Some(Ok(send_terminate_game_message(
@@ -255,6 +411,7 @@ pub fn game_handler(
             return Some(Ok(SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type,
+                attacker_instance_id: Some(instance_id),
                 is_alive: Some(is_attacker_alive),
 
                 attacker_health: None,
@@ -266,48 +423,42 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: false,
                 message: Some(String::from("Is Mine Response")),
+                result: None,
             }));
         }
         ActionType::PlaceBombs => {
-            let attacker_delta: Vec<Coords> = socket_request.attacker_path.clone();
             let current_pos = socket_request.start_position.unwrap();
             let bomb_coords = socket_request.bomb_position;
+            let Some(bomb_id) = socket_request.bomb_id else {
+                return Some(Err(anyhow::anyhow!("Bomb type missing in PlaceBombs")));
+            };
 
-            if _game_state.bombs.total_count == 0 {
+            // Running out of one type is checked by `place_bombs`; the game ends once all are gone
+            let bombs_left: i32 = _game_state
+                .bombs
+                .values()
+                .map(|bomb| bomb.total_count)
+                .sum();
+            if bombs_left == 0 {
                 return Some(Ok(send_terminate_game_message(
                     socket_request.frame_number,
                     "No bombs left".to_string(),
                 )));
             }
 
-            for coord in attacker_delta.clone() {
-                let mut direction = Direction::Up;
-
-                let prev_pos = _game_log.e.last().unwrap().coords;
-                if prev_pos.x < coord.x {
-                    direction = Direction::Down;
-                } else if prev_pos.x > coord.x {
-                    direction = Direction::Up;
-                } else if prev_pos.y < coord.y {
-                    direction = Direction::Left;
-                } else if prev_pos.y > coord.y {
-                    direction = Direction::Right;
-                }
-
-                let event_response = EventResponse {
-                    attacker_id: None,
-                    bomb_id: None,
-                    coords: coord,
-                    direction,
-                    is_bomb: coord == bomb_coords,
-                };
-
-                _game_log.e.push(event_response.clone());
-            }
-
-            buildings_damaged_result = _game_state.place_bombs(current_pos, bomb_coords);
+            buildings_damaged_result = _game_state.place_bombs(
+                socket_request.frame_number,
+                instance_id,
+                bomb_id,
+                current_pos,
+                bomb_coords,
+            );
 
             _game_log.r.b += 1;
             _game_log.r.d = _game_state.damage_percentage as i32;
@@ -333,6 +484,7 @@ pub fn game_handler(
             return Some(Ok(SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type,
+                attacker_instance_id: Some(instance_id),
                 is_alive: Some(true),
 
                 attacker_health: None,
@@ -344,14 +496,53 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: false,
                 message: Some(String::from("Place Bomb Response")),
+                result: None,
+            }));
+        }
+        ActionType::SetLoadout => {
+            let loadout = socket_request.bomb_loadout.unwrap_or_default();
+            _game_state.set_loadout(socket_request.frame_number, &loadout, _bomb_types);
+
+            if _game_state.in_validation.is_invalidated {
+                return Some(Ok(send_terminate_game_message(
+                    socket_request.frame_number,
+                    _game_state.in_validation.message.clone(),
+                )));
+            }
+
+            return Some(Ok(SocketResponse {
+                frame_number: socket_request.frame_number,
+                result_type: ResultType::Nothing,
+                attacker_instance_id: None,
+                is_alive: None,
+                attacker_health: None,
+                exploded_mines: None,
+                defender_damaged: None,
+                damaged_buildings: None,
+                hut_triggered: false,
+                hut_defenders: None,
+                total_damage_percentage: Some(_game_state.damage_percentage),
+                is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
+                is_game_over: false,
+                message: Some(String::from("Bomb loadout set")),
+                result: None,
             }));
         }
         ActionType::Idle => {
             return Some(Ok(SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type: ResultType::Nothing,
+                attacker_instance_id: Some(instance_id),
                 is_alive: Some(true),
 
                 attacker_health: None,
@@ -363,14 +554,20 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: false,
                 message: Some(String::from("Idle Response")),
+                result: None,
             }));
         }
         ActionType::Terminate => {
             let socket_response = SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type: ResultType::GameOver,
+                attacker_instance_id: Some(instance_id),
                 is_alive: None,
                 attacker_health: None,
                 exploded_mines: None,
@@ -381,17 +578,23 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: true,
                 message: Some(String::from("Game over")),
+                result: None,
             };
 
             return Some(Ok(socket_response));
         }
         ActionType::SelfDestruct => {
-            _game_state.self_destruct();
+            _game_state.self_destruct(instance_id);
             let socket_response = SocketResponse {
                 frame_number: socket_request.frame_number,
                 result_type: ResultType::Nothing,
+                attacker_instance_id: Some(instance_id),
                 is_alive: Some(false),
                 attacker_health: None,
                 exploded_mines: None,
@@ -402,8 +605,13 @@ pub fn game_handler(
                 hut_defenders: None,
                 total_damage_percentage: Some(_game_state.damage_percentage),
                 is_sync: false,
+                remaining_time_in_millis: None,
+                error_code: None,
+                state_checksum: None,
+                state: None,
                 is_game_over: false,
                 message: Some(String::from("Self Destructed")),
+                result: None,
             };
 
             return Some(Ok(socket_response));
@@ -411,3 +619,213 @@ pub fn game_handler(
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::defense::util::SimulationBaseResponse,
+        validator::{
+            simulation::simulated_game_log,
+            util::{BuildingDetails, ValidationPolicy},
+        },
+    };
+
+    struct Game {
+        attacker_types: HashMap<i32, AttackerType>,
+        state: State,
+        shortest_paths: HashMap<SourceDestXY, Coords>,
+        roads: HashSet<(i32, i32)>,
+        bomb_types: Vec<BombType>,
+        log: GameLog,
+    }
+
+    impl Game {
+        // A 2x2 building at the origin, with a road running along its bottom edge
+        fn new() -> Game {
+            let attacker_type = AttackerType {
+                id: 1,
+                max_health: 100,
+                speed: 1,
+                amt_of_emps: 3,
+                level: 1,
+                cost: 0,
+                name: String::from("Attacker"),
+                prop_id: 1,
+            };
+            let building = BuildingDetails {
+                id: 1,
+                current_hp: 100,
+                total_hp: 100,
+                artifacts_obtained: 100,
+                tile: Coords { x: 0, y: 0 },
+                width: 2,
+                name: String::from("Bank"),
+                range: 0,
+                frequency: 0,
+                block_id: 1,
+                loot_percentage: 30,
+            };
+            let mut state = State::new(
+                2,
+                3,
+                Vec::new(),
+                HashMap::new(),
+                Vec::new(),
+                vec![building],
+                3,
+            );
+            state.set_total_hp_buildings();
+
+            Game {
+                attacker_types: HashMap::from([(attacker_type.id, attacker_type)]),
+                state,
+                shortest_paths: HashMap::new(),
+                roads: (0..3).map(|x| (x, 2)).collect(),
+                bomb_types: vec![BombType {
+                    id: 1,
+                    radius: 1,
+                    damage: 10,
+                    total_count: 0,
+                }],
+                log: simulated_game_log(
+                    2,
+                    3,
+                    SimulationBaseResponse {
+                        m: 0,
+                        ms: Vec::new(),
+                        b: Vec::new(),
+                        d: Vec::new(),
+                        mt: Vec::new(),
+                        at: Vec::new(),
+                        bt: Vec::new(),
+                    },
+                ),
+            }
+        }
+
+        fn run(&mut self, actions: Vec<SocketRequest>) -> Result<SocketResponse> {
+            batch_handler(
+                &self.attacker_types,
+                BatchRequest { actions },
+                &mut self.state,
+                &self.shortest_paths,
+                &self.roads,
+                &self.bomb_types,
+                &mut self.log,
+            )
+            .unwrap()
+        }
+    }
+
+    fn action(frame_number: i32, action_type: ActionType, instance_id: i32) -> SocketRequest {
+        SocketRequest {
+            frame_number,
+            action_type,
+            attacker_id: Some(1),
+            attacker_instance_id: Some(instance_id),
+            bomb_id: Some(1),
+            start_position: Some(Coords { x: 0, y: 2 }),
+            attacker_path: Vec::new(),
+            bomb_position: Coords { x: 0, y: 2 },
+            is_game_over: None,
+            bomb_loadout: None,
+            state_checksum: None,
+        }
+    }
+
+    fn place_and_bomb() -> Vec<SocketRequest> {
+        vec![
+            action(1, ActionType::PlaceAttacker, 0),
+            action(1, ActionType::PlaceBombs, 0),
+        ]
+    }
+
+    #[test]
+    fn batch_applies_every_action() {
+        let mut game = Game::new();
+
+        let response = game.run(place_and_bomb()).unwrap();
+
+        assert_eq!(response.result_type, ResultType::Batch);
+        assert_eq!(response.damaged_buildings.unwrap().len(), 1);
+        assert_eq!(game.state.attackers_deployed, 1);
+        assert_eq!(game.state.buildings[0].current_hp, 75);
+        assert_eq!(game.state.damage_percentage, 25.0);
+        assert_eq!(game.log.e.len(), 2);
+        assert_eq!((game.log.r.au, game.log.r.b), (1, 1));
+    }
+
+    #[test]
+    fn failed_action_rolls_back_the_whole_batch() {
+        let mut game = Game::new();
+        let mut actions = place_and_bomb();
+        // Instance 7 was never deployed
+        actions.push(action(2, ActionType::MoveAttacker, 7));
+
+        assert!(game.run(actions).is_err());
+
+        assert!(game.state.attackers.is_empty());
+        assert_eq!(game.state.attackers_deployed, 0);
+        assert!(game.state.bombs.is_empty());
+        assert_eq!(game.state.buildings[0].current_hp, 100);
+        assert_eq!(game.state.damage_percentage, 0.0);
+        assert!(game.log.e.is_empty());
+        assert_eq!((game.log.r.au, game.log.r.b, game.log.r.d), (0, 0, 0));
+    }
+
+    #[test]
+    fn rollback_keeps_earlier_batches() {
+        let mut game = Game::new();
+        game.run(place_and_bomb()).unwrap();
+
+        let actions = vec![
+            action(2, ActionType::PlaceBombs, 0),
+            action(2, ActionType::MoveAttacker, 7),
+        ];
+        assert!(game.run(actions).is_err());
+
+        assert_eq!(game.state.buildings[0].current_hp, 75);
+        assert_eq!(game.state.bombs[&1].total_count, 2);
+        assert_eq!(game.log.e.len(), 2);
+        assert_eq!(game.log.r.b, 1);
+    }
+
+    #[test]
+    fn empty_or_unordered_batches_are_rejected() {
+        let mut game = Game::new();
+        assert!(game.run(Vec::new()).is_err());
+
+        let mut actions = place_and_bomb();
+        actions.reverse();
+        actions[0].frame_number = 2;
+        assert!(game.run(actions).is_err());
+
+        assert_eq!(game.state.attackers_deployed, 0);
+        assert!(game.log.e.is_empty());
+    }
+
+    #[test]
+    fn off_road_and_too_fast_moves_are_flagged() {
+        let mut game = Game::new();
+        game.state.set_validation_policy(ValidationPolicy::Warn);
+        game.run(vec![action(1, ActionType::PlaceAttacker, 0)])
+            .unwrap();
+
+        let mut off_road = action(2, ActionType::MoveAttacker, 0);
+        off_road.attacker_path = vec![Coords { x: 0, y: 2 }, Coords { x: 0, y: 3 }];
+        game.run(vec![off_road]).unwrap();
+
+        let mut too_fast = action(3, ActionType::MoveAttacker, 0);
+        too_fast.attacker_path = (0..3).map(|x| Coords { x, y: 2 }).collect();
+        game.run(vec![too_fast]).unwrap();
+
+        let messages: Vec<&str> = game
+            .state
+            .invalidations
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect();
+        assert_eq!(messages, ["attacker out of road", "attacker speed abuse"]);
+    }
+}
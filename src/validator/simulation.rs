@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        attack::{
+            socket::{ActionType, ResultType, SocketRequest},
+            util::{GameEvent, GameLog, ResultResponse},
+        },
+        defense::{shortest_path::shortest_paths_from_roads, util::SimulationBaseResponse},
+    },
+    constants::{REPLAY_LOG_VERSION, ROAD_ID},
+    models::{AttackerType, User},
+};
+
+use super::{
+    game_handler,
+    state::State,
+    util::{
+        BombType, BuildingDetails, Coords, DefenderDetails, InValidationRecord, MineDetails,
+        ValidationPolicy,
+    },
+};
+
+/// A recorded attack: the defender base as it was served for the game, and the requests the
+/// attacker sent, in the order they were received.
+#[derive(Deserialize)]
+pub struct SimulationInput {
+    #[serde(default)]
+    pub attacker_id: i32,
+    #[serde(default)]
+    pub defender_id: i32,
+    #[serde(default)]
+    pub validation_policy: ValidationPolicy,
+    // `no_of_attackers` of the fixture the game was played under; unlimited if absent
+    pub max_attackers: Option<i32>,
+    pub base: SimulationBaseResponse,
+    pub requests: Vec<SocketRequest>,
+}
+
+#[derive(Serialize)]
+pub struct SimulationReport {
+    pub damage_percentage: f32,
+    pub artifacts: i32,
+    pub bombs_used: i32,
+    pub attackers_used: i32,
+    pub requests_processed: usize,
+    pub is_game_over: bool,
+    pub invalidations: Vec<InValidationRecord>,
+    pub events: Vec<GameEvent>,
+}
+
+/// Replays a recorded attack through `game_handler` with no socket, Redis or Postgres.
+///
+/// Every defender, mine, building and hut defender is rebuilt from the base the same way the
+/// socket handler loads them from the database. Processing stops at the first game over
+/// response, as it would on the live socket.
+pub fn simulate_attack(input: SimulationInput) -> Result<SimulationReport> {
+    let base = &input.base;
+
+    let attacker_types: HashMap<i32, AttackerType> = base
+        .at
+        .iter()
+        .map(|attacker_type| (attacker_type.id, attacker_type.clone()))
+        .collect();
+    let bomb_types: Vec<BombType> = base
+        .bt
+        .iter()
+        .map(|emp| BombType {
+            id: emp.id,
+            radius: emp.attack_radius,
+            damage: emp.attack_damage,
+            total_count: 0,
+        })
+        .collect();
+
+    let roads = get_roads(base);
+    let roads_list: Vec<(i32, i32)> = roads.iter().cloned().collect();
+    let shortest_paths = shortest_paths_from_roads(&roads_list);
+
+    let mut game_state = State::new(
+        input.attacker_id,
+        input.defender_id,
+        get_defenders(base),
+        get_hut_defenders(base)?,
+        get_mines(base),
+        get_buildings(base),
+        input.max_attackers.unwrap_or(i32::MAX),
+    );
+    game_state.set_total_hp_buildings();
+    game_state.set_validation_policy(input.validation_policy);
+
+    let mut game_log = simulated_game_log(input.attacker_id, input.defender_id, input.base.clone());
+
+    let mut requests_processed = 0;
+    let mut is_game_over = false;
+
+    for request in input.requests {
+        check_request(&request, &attacker_types)?;
+        let response = game_handler(
+            &attacker_types,
+            request,
+            &mut game_state,
+            &shortest_paths,
+            &roads,
+            &bomb_types,
+            &mut game_log,
+        );
+        requests_processed += 1;
+
+        if let Some(response) = response {
+            if response?.result_type == ResultType::GameOver {
+                is_game_over = true;
+                break;
+            }
+        }
+    }
+
+    Ok(SimulationReport {
+        damage_percentage: game_state.damage_percentage,
+        artifacts: game_state.artifacts,
+        bombs_used: game_log.r.b,
+        attackers_used: game_log.r.au,
+        requests_processed,
+        is_game_over,
+        invalidations: game_state.invalidations,
+        events: game_log.e,
+    })
+}
+
+/// `game_handler` trusts the socket client for these fields, so reject them up front rather
+/// than panicking halfway through a replay.
+fn check_request(
+    request: &SocketRequest,
+    attacker_types: &HashMap<i32, AttackerType>,
+) -> Result<()> {
+    let needs_position = matches!(
+        request.action_type,
+        ActionType::PlaceAttacker | ActionType::MoveAttacker | ActionType::PlaceBombs
+    );
+    if needs_position && request.start_position.is_none() {
+        bail!("Frame {} has no start position", request.frame_number);
+    }
+    if let Some(attacker_id) = request.attacker_id {
+        if !attacker_types.contains_key(&attacker_id) {
+            bail!(
+                "Frame {} uses unknown attacker type {}",
+                request.frame_number,
+                attacker_id
+            );
+        }
+    }
+    Ok(())
+}
+
+// Defender and mine blocks sit on road tiles, same as `get_valid_road_paths`
+fn get_roads(base: &SimulationBaseResponse) -> HashSet<(i32, i32)> {
+    let road_blocks: HashSet<i32> = base
+        .b
+        .iter()
+        .filter(|building| building.id == ROAD_ID)
+        .map(|building| building.block_id)
+        .chain(base.d.iter().map(|defender| defender.block_id))
+        .chain(base.mt.iter().map(|mine| mine.block_id))
+        .collect();
+
+    base.ms
+        .iter()
+        .filter(|map_space| road_blocks.contains(&map_space.block_type_id))
+        .map(|map_space| (map_space.x_coordinate, map_space.y_coordinate))
+        .collect()
+}
+
+fn get_defenders(base: &SimulationBaseResponse) -> Vec<DefenderDetails> {
+    let mut defenders = Vec::new();
+    for map_space in base.ms.iter() {
+        if let Some(defender) = base
+            .d
+            .iter()
+            .find(|defender| defender.block_id == map_space.block_type_id)
+        {
+            defenders.push(DefenderDetails {
+                id: defender.id,
+                radius: defender.radius,
+                speed: defender.speed,
+                damage: defender.damage,
+                defender_pos: Coords {
+                    x: map_space.x_coordinate,
+                    y: map_space.y_coordinate,
+                },
+                is_alive: true,
+                damage_dealt: false,
+                target_id: None,
+                target_attacker: None,
+                path_in_current_frame: Vec::new(),
+                block_id: defender.block_id,
+                level: defender.level,
+            });
+        }
+    }
+    defenders
+}
+
+fn get_hut_defenders(base: &SimulationBaseResponse) -> Result<HashMap<i32, DefenderDetails>> {
+    let mut hut_defenders = HashMap::new();
+    for map_space in base.ms.iter() {
+        let hut = base.b.iter().find(|building| {
+            building.block_id == map_space.block_type_id && building.name == "Defender_Hut"
+        });
+        if let Some(hut) = hut {
+            let hut_defender = base
+                .d
+                .iter()
+                .find(|defender| defender.name == "Hut_Defender" && defender.level == hut.level);
+            let Some(hut_defender) = hut_defender else {
+                bail!("No Hut_Defender of level {} in base", hut.level);
+            };
+            hut_defenders.insert(
+                map_space.id,
+                DefenderDetails {
+                    id: hut_defender.id,
+                    radius: hut_defender.radius,
+                    speed: hut_defender.speed,
+                    damage: hut_defender.damage,
+                    defender_pos: Coords { x: 0, y: 0 },
+                    is_alive: true,
+                    damage_dealt: false,
+                    target_id: None,
+                    target_attacker: None,
+                    path_in_current_frame: Vec::new(),
+                    block_id: hut_defender.block_id,
+                    level: hut_defender.level,
+                },
+            );
+        }
+    }
+    Ok(hut_defenders)
+}
+
+fn get_mines(base: &SimulationBaseResponse) -> Vec<MineDetails> {
+    base.ms
+        .iter()
+        .filter_map(|map_space| {
+            base.mt
+                .iter()
+                .find(|mine| mine.block_id == map_space.block_type_id)
+                .map(|mine| (map_space, mine))
+        })
+        .enumerate()
+        .map(|(mine_id, (map_space, mine))| MineDetails {
+            id: mine_id as i32,
+            position: Coords {
+                x: map_space.x_coordinate,
+                y: map_space.y_coordinate,
+            },
+            radius: mine.radius,
+            damage: mine.damage,
+        })
+        .collect()
+}
+
+fn get_buildings(base: &SimulationBaseResponse) -> Vec<BuildingDetails> {
+    base.ms
+        .iter()
+        .filter_map(|map_space| {
+            base.b
+                .iter()
+                .find(|building| {
+                    building.block_id == map_space.block_type_id && building.id != ROAD_ID
+                })
+                .map(|building| BuildingDetails {
+                    id: map_space.id,
+                    current_hp: building.hp,
+                    total_hp: building.hp,
+                    artifacts_obtained: map_space.artifacts.unwrap_or(0),
+                    tile: Coords {
+                        x: map_space.x_coordinate,
+                        y: map_space.y_coordinate,
+                    },
+                    width: building.width,
+                    name: building.name.clone(),
+                    range: building.range,
+                    frequency: building.frequency,
+                    block_id: building.block_id,
+                    loot_percentage: building.loot_percentage,
+                })
+        })
+        .collect()
+}
+
+/// An empty log of a game that was never stored, with placeholder players.
+pub(crate) fn simulated_game_log(
+    attacker_id: i32,
+    defender_id: i32,
+    base: SimulationBaseResponse,
+) -> GameLog {
+    GameLog {
+        v: REPLAY_LOG_VERSION,
+        g: 0,
+        a: simulated_user(attacker_id),
+        d: simulated_user(defender_id),
+        b: base,
+        e: Vec::new(),
+        r: ResultResponse {
+            d: 0,
+            a: 0,
+            b: 0,
+            au: 0,
+            na: 0,
+            nd: 0,
+            oa: 0,
+            od: 0,
+            ls: Vec::new(),
+            lu: 0,
+            lx: 0,
+        },
+    }
+}
+
+fn simulated_user(id: i32) -> User {
+    User {
+        id,
+        name: String::new(),
+        email: String::new(),
+        username: String::new(),
+        is_pragyan: false,
+        attacks_won: 0,
+        defenses_won: 0,
+        trophies: 0,
+        avatar_id: 0,
+        artifacts: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bank and a plain building by a road with a mine on it. The attacker bombs the bank down
+    // (60 of its 200 artifacts at 30%), walks over the mine and half destroys the building.
+    const BANK_RAID: &str = include_str!("recordings/bank_raid.json");
+
+    fn bank_raid() -> SimulationInput {
+        serde_json::from_str(BANK_RAID).unwrap()
+    }
+
+    #[test]
+    fn recorded_attack_replays_to_the_same_result() {
+        let report = simulate_attack(bank_raid()).unwrap();
+
+        assert_eq!(report.damage_percentage, 75.0);
+        assert_eq!(report.artifacts, 60);
+        assert_eq!(report.bombs_used, 3);
+        assert_eq!(report.attackers_used, 1);
+        assert_eq!(report.requests_processed, 10);
+        assert!(report.is_game_over);
+        assert!(report.invalidations.is_empty());
+
+        let exploded_mines = report
+            .events
+            .iter()
+            .filter(|event| event.s.result_type == ResultType::MinesExploded)
+            .count();
+        assert_eq!(exploded_mines, 1);
+    }
+
+    #[test]
+    fn replay_stops_at_game_over() {
+        let mut input = bank_raid();
+        let mut extra_bomb = input.requests[1].clone();
+        extra_bomb.frame_number = 7;
+        input.requests.push(extra_bomb);
+
+        let report = simulate_attack(input).unwrap();
+
+        assert_eq!(report.requests_processed, 10);
+        assert_eq!(report.bombs_used, 3);
+    }
+
+    #[test]
+    fn unknown_attacker_type_is_rejected() {
+        let mut input = bank_raid();
+        input.requests[0].attacker_id = Some(99);
+
+        assert!(simulate_attack(input).is_err());
+    }
+}
@@ -1,30 +1,35 @@
 use std::{
     cmp::max,
     collections::{HashMap, HashSet},
-    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::constants::{BOMB_DAMAGE_MULTIPLIER, LEVEL, LIVES, PERCENTANGE_ARTIFACTS_OBTAINABLE};
+use crate::constants::{BOMB_DAMAGE_MULTIPLIER, LEVEL, MAX_BOMBS_PER_ATTACK};
 use crate::{
-    api::attack::socket::{BuildingResponse, DefenderResponse},
+    api::attack::socket::{BombLoadout, BuildingResponse, DefenderResponse, GameStateResponse},
     validator::util::{
         Attacker, BuildingDetails, Coords, DefenderDetails, DefenderReturnType, InValidation,
-        MineDetails, SourceDestXY,
+        InValidationRecord, MineDetails, SourceDestXY, StateChecksum, ValidationPolicy,
     },
 };
 
 use serde::{Deserialize, Serialize};
 
-use super::util::{select_side_hut_defender, BombType, HutDefenderDetails};
+use super::util::{
+    lootable_artifacts, millis_to_frames, select_side_hut_defender, BombType, HutDefenderDetails,
+};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct State {
     pub frame_no: i32,
     pub attacker_user_id: i32,
     pub defender_user_id: i32,
-    pub attacker: Option<Attacker>,
+    pub attackers: HashMap<i32, Attacker>,
+    pub attackers_deployed: i32,
+    pub max_attackers: i32,
+    pub moved_in_frame: HashSet<i32>,
     pub attacker_death_count: i32,
-    pub bombs: BombType,
+    pub bombs: HashMap<i32, BombType>,
+    pub is_loadout_set: bool,
     pub damage_percentage: f32,
     pub artifacts: i32,
     pub defenders: Vec<DefenderDetails>,
@@ -33,6 +38,9 @@ pub struct State {
     pub buildings: Vec<BuildingDetails>,
     pub total_hp_buildings: i32,
     pub in_validation: InValidation,
+    pub validation_policy: ValidationPolicy,
+    pub invalidations: Vec<InValidationRecord>,
+    pub latest_checksum: Option<StateChecksum>,
 }
 
 impl State {
@@ -43,6 +51,7 @@ impl State {
         hut_defenders: HashMap<i32, DefenderDetails>,
         mines: Vec<MineDetails>,
         buildings: Vec<BuildingDetails>,
+        max_attackers: i32,
     ) -> State {
         let mut hut = HashMap::new();
         for building in buildings.clone() {
@@ -55,7 +64,7 @@ impl State {
                     hut_defender: hut_defenders.get(&building.id).unwrap().clone(),
                     hut_triggered: false,
                     hut_defenders_count: defenders_count,
-                    hut_defender_latest_time: None,
+                    hut_defender_latest_frame: None,
                 };
                 hut.insert(building.id, hut_defender_details);
             }
@@ -64,14 +73,13 @@ impl State {
             frame_no: 0,
             attacker_user_id,
             defender_user_id,
-            attacker: None,
+            attackers: HashMap::new(),
+            attackers_deployed: 0,
+            max_attackers,
+            moved_in_frame: HashSet::new(),
             attacker_death_count: 0,
-            bombs: BombType {
-                id: -1,
-                radius: 0,
-                damage: 0,
-                total_count: 0,
-            },
+            bombs: HashMap::new(),
+            is_loadout_set: false,
             damage_percentage: 0.0,
             artifacts: 0,
             defenders,
@@ -83,17 +91,163 @@ impl State {
                 message: "".to_string(),
                 is_invalidated: false,
             },
+            validation_policy: ValidationPolicy::default(),
+            invalidations: Vec::new(),
+            latest_checksum: None,
+        }
+    }
+
+    pub fn set_validation_policy(&mut self, validation_policy: ValidationPolicy) {
+        self.validation_policy = validation_policy;
+    }
+
+    /// Records a validator flag. Unless the policy is `Warn`, this also marks the game as
+    /// invalidated, which ends it on the next response.
+    pub fn invalidate(&mut self, frame_no: i32, message: &str) {
+        log::info!(
+            "Game of attacker:{} and defender:{} flagged at frame {}: {}",
+            self.attacker_user_id,
+            self.defender_user_id,
+            frame_no,
+            message
+        );
+
+        // A repeated flag (e.g. every frame after a skipped one) is only recorded once
+        if self
+            .invalidations
+            .last()
+            .map(|record| record.message.as_str())
+            != Some(message)
+        {
+            self.invalidations.push(InValidationRecord {
+                frame_number: frame_no,
+                message: message.to_string(),
+            });
+        }
+
+        if self.validation_policy != ValidationPolicy::Warn {
+            self.in_validation = InValidation {
+                message: message.to_string(),
+                is_invalidated: true,
+            };
+        }
+    }
+
+    /// Flags the game so that `terminate_game` voids it whatever the configured policy.
+    pub fn void(&mut self, frame_no: i32, message: &str) {
+        self.validation_policy = ValidationPolicy::VoidResult;
+        self.invalidate(frame_no, message);
+    }
+
+    pub fn self_destruct(&mut self, instance_id: i32) {
+        if let Some(attacker) = self.attackers.get_mut(&instance_id) {
+            if attacker.attacker_health > 0 {
+                self.attacker_death_count += 1;
+            }
+            attacker.attacker_health = 0;
         }
+        self.release_defenders(instance_id);
     }
 
-    pub fn self_destruct(&mut self) {
-        self.attacker_death_count += 1;
-        self.attacker.as_mut().unwrap().attacker_health = 0;
+    // Stops every defender chasing the given attacker
+    fn release_defenders(&mut self, instance_id: i32) {
         for defender in self.defenders.iter_mut() {
-            defender.target_id = None;
+            if defender.target_attacker == Some(instance_id) {
+                defender.target_id = None;
+                defender.target_attacker = None;
+            }
+        }
+    }
+
+    pub fn is_attacker_alive(&self, instance_id: i32) -> bool {
+        self.attackers
+            .get(&instance_id)
+            .is_some_and(|attacker| attacker.attacker_health > 0)
+    }
+
+    pub fn game_state_response(&self) -> GameStateResponse {
+        GameStateResponse {
+            frame_no: self.frame_no,
+            attacker_user_id: self.attacker_user_id,
+            defender_user_id: self.defender_user_id,
+            attackers: self.attackers.clone(),
+            attacker_death_count: self.attacker_death_count,
+            bombs: self.bombs.values().cloned().collect(),
+            damage_percentage: self.damage_percentage,
+            artifacts: self.artifacts,
+            defenders: self.defenders.clone(),
+            mines: self.mines.clone(),
+            buildings: self.buildings.clone(),
+            total_hp_buildings: self.total_hp_buildings,
         }
     }
 
+    /// The state as the attacker sees it: mines not yet blown are hidden from the attack view, so
+    /// they are left out here too.
+    pub fn attacker_state_response(&self) -> GameStateResponse {
+        GameStateResponse {
+            mines: Vec::new(),
+            ..self.game_state_response()
+        }
+    }
+
+    /// FNV-1a (32 bit) over the little-endian bytes of, in order: every attacker by instance id
+    /// (instance id, x, y, health), every defender in base order (alive as 1 or 0, x, y), every
+    /// building in base order (id, current hp). Mines are left out, as the attacker cannot see
+    /// them until they blow. Clients compute the same to check they are in sync.
+    pub fn checksum(&self) -> u32 {
+        const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+        const FNV_PRIME: u32 = 0x01000193;
+
+        let mut values: Vec<i32> = Vec::new();
+        let mut instance_ids: Vec<&i32> = self.attackers.keys().collect();
+        instance_ids.sort();
+        for instance_id in instance_ids {
+            let attacker = &self.attackers[instance_id];
+            values.extend([
+                *instance_id,
+                attacker.attacker_pos.x,
+                attacker.attacker_pos.y,
+                attacker.attacker_health,
+            ]);
+        }
+        for defender in self.defenders.iter() {
+            values.extend([
+                defender.is_alive as i32,
+                defender.defender_pos.x,
+                defender.defender_pos.y,
+            ]);
+        }
+        for building in self.buildings.iter() {
+            values.extend([building.id, building.current_hp]);
+        }
+
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    pub fn record_checksum(&mut self, frame_no: i32) -> StateChecksum {
+        let checksum = StateChecksum {
+            frame_number: frame_no,
+            value: self.checksum(),
+        };
+        self.latest_checksum = Some(checksum);
+        checksum
+    }
+
+    /// Whether the client's checksum disagrees with the one recorded for the same frame. A
+    /// checksum for an older checkpoint is not compared.
+    pub fn is_desynced(&self, client_checksum: &StateChecksum) -> bool {
+        self.latest_checksum.is_some_and(|checksum| {
+            checksum.frame_number == client_checksum.frame_number
+                && checksum.value != client_checksum.value
+        })
+    }
+
     pub fn set_total_hp_buildings(&mut self) {
         let mut total_hp = 0;
         for building in self.buildings.iter() {
@@ -102,31 +256,85 @@ impl State {
         self.total_hp_buildings = total_hp;
     }
 
-    pub fn set_bombs(&mut self, bomb_type: BombType, bombs: i32) {
-        self.bombs = BombType {
-            id: bomb_type.id,
-            radius: bomb_type.radius,
-            damage: bomb_type.damage,
-            total_count: bombs,
-        };
+    /// Picks the bombs for the whole attack. Only allowed once, before the first attacker is
+    /// deployed; every type must be one the attacker owns and the total is capped at
+    /// `MAX_BOMBS_PER_ATTACK`.
+    pub fn set_loadout(&mut self, frame_no: i32, loadout: &[BombLoadout], bomb_types: &[BombType]) {
+        if self.is_loadout_set || self.attackers_deployed > 0 {
+            self.invalidate(frame_no, "Bomb loadout changed mid attack");
+            return;
+        }
+
+        let mut bombs: HashMap<i32, BombType> = HashMap::new();
+        let mut total_bombs = 0;
+        for entry in loadout {
+            let Some(bomb_type) = bomb_types.iter().find(|bomb| bomb.id == entry.bomb_id) else {
+                self.invalidate(frame_no, "Bomb type not owned");
+                return;
+            };
+            if entry.count <= 0 {
+                self.invalidate(frame_no, "Bomb Count forged");
+                return;
+            }
+            total_bombs += entry.count;
+            bombs
+                .entry(bomb_type.id)
+                .or_insert(BombType {
+                    total_count: 0,
+                    ..bomb_type.clone()
+                })
+                .total_count += entry.count;
+        }
+
+        if total_bombs > MAX_BOMBS_PER_ATTACK {
+            self.invalidate(frame_no, "Bomb loadout exceeds limit");
+            return;
+        }
+
+        self.bombs = bombs;
+        self.is_loadout_set = true;
     }
-    pub fn place_attacker(&mut self, attacker: Attacker) {
-        self.attacker = Some(attacker);
-        // println!("defnders: {:?}",self.defenders);
+
+    // Clients that don't send a loadout get each attacker's bombs of the type picked on placement
+    pub fn set_bombs(&mut self, bomb_type: BombType, bombs: i32) {
+        if self.is_loadout_set {
+            return;
+        }
+        let total_bombs: i32 = self.bombs.values().map(|bomb| bomb.total_count).sum();
+        let bombs = bombs.min(MAX_BOMBS_PER_ATTACK - total_bombs).max(0);
+        self.bombs
+            .entry(bomb_type.id)
+            .or_insert(BombType {
+                total_count: 0,
+                ..bomb_type
+            })
+            .total_count += bombs;
     }
 
-    pub fn mine_blast_update(&mut self, _id: i32, damage_to_attacker: i32) {
-        let attacker = self.attacker.as_mut().unwrap();
+    /// Deploys an attacker under the client's instance id. An instance id can only be reused
+    /// once its previous attacker is dead, and every deployment counts against the fixture's
+    /// `no_of_attackers`.
+    pub fn place_attacker(&mut self, frame_no: i32, instance_id: i32, attacker: Attacker) {
+        if self.is_attacker_alive(instance_id) {
+            self.invalidate(frame_no, "Attacker instance already deployed");
+        }
+        if self.attackers_deployed >= self.max_attackers {
+            self.invalidate(frame_no, "Attacker count forged");
+        }
+        self.attackers_deployed += 1;
+        self.attackers.insert(instance_id, attacker);
+    }
 
-        if attacker.attacker_health > 0 {
-            attacker.attacker_health =
-                std::cmp::max(0, attacker.attacker_health - damage_to_attacker);
-            if attacker.attacker_health == 0 {
-                self.attacker_death_count += 1;
-                for defender in self.defenders.iter_mut() {
-                    defender.target_id = None;
+    pub fn mine_blast_update(&mut self, instance_id: i32, _id: i32, damage_to_attacker: i32) {
+        if let Some(attacker) = self.attackers.get_mut(&instance_id) {
+            if attacker.attacker_health > 0 {
+                attacker.attacker_health =
+                    std::cmp::max(0, attacker.attacker_health - damage_to_attacker);
+                if attacker.attacker_health == 0 {
+                    self.attacker_death_count += 1;
+                    attacker.attacker_pos = Coords { x: -1, y: -1 };
+                    self.release_defenders(instance_id);
                 }
-                attacker.attacker_pos = Coords { x: -1, y: -1 };
             }
         }
 
@@ -134,6 +342,9 @@ impl State {
     }
 
     pub fn update_frame_number(&mut self, frame_no: i32) {
+        if frame_no != self.frame_no {
+            self.moved_in_frame.clear();
+        }
         self.frame_no = frame_no;
     }
 
@@ -141,40 +352,32 @@ impl State {
         &mut self,
         frame_no: i32,
         roads: &HashSet<(i32, i32)>,
+        instance_id: i32,
         attacker_current: Attacker,
     ) -> Option<Attacker> {
-        if (frame_no - self.frame_no) != 1 {
-            self.in_validation = InValidation {
-                message: "Frame number mismatch".to_string(),
-                is_invalidated: true,
-            };
-            // GAME_OVER
+        // Every deployed attacker moves once per frame, all under the same frame number
+        let is_next_frame = frame_no - self.frame_no == 1;
+        let is_same_frame =
+            frame_no == self.frame_no && !self.moved_in_frame.contains(&instance_id);
+        if !is_next_frame && !is_same_frame {
+            self.invalidate(frame_no, "Frame number mismatch");
         }
 
-        if self.attacker_death_count == LIVES {
-            self.in_validation = InValidation {
-                message: "Attacker Lives forged!".to_string(),
-                is_invalidated: true,
-            };
+        if self.attacker_death_count >= self.max_attackers {
+            self.invalidate(frame_no, "Attacker Lives forged!");
         }
 
         for coord in attacker_current.path_in_current_frame.clone().into_iter() {
             if !roads.contains(&(coord.x, coord.y)) {
-                // GAME_OVER
-
-                println!("attacker out of road at {} frame", frame_no);
+                self.invalidate(frame_no, "attacker out of road");
             }
         }
 
         let mut attacker = attacker_current.clone();
 
-        if attacker.attacker_speed + 1 != attacker.path_in_current_frame.len() as i32 {
-            println!(
-                "attacker speed abuse at {} frame --- speed  :{}, length: {}",
-                frame_no,
-                attacker.attacker_speed,
-                attacker.path_in_current_frame.len()
-            );
+        // A frame's path holds the starting tile plus at most `attacker_speed` steps
+        if attacker.path_in_current_frame.len() as i32 > attacker.attacker_speed + 1 {
+            self.invalidate(frame_no, "attacker speed abuse");
         }
 
         let mut coord_temp: Coords = Coords {
@@ -192,12 +395,7 @@ impl State {
                 || ((coord_temp.x - coord.x).abs() == 1 && coord_temp.y != coord.y)
                 || ((coord_temp.y - coord.y).abs() == 1 && coord_temp.x != coord.x)
             {
-                // GAME_OVER
-                // println!("attacker skipped a tile at {} frame", frame_no);
-                self.in_validation = InValidation {
-                    message: "attacker skipped a tile".to_string(),
-                    is_invalidated: true,
-                };
+                self.invalidate(frame_no, "attacker skipped a tile");
             }
 
             let new_pos = coord;
@@ -237,6 +435,7 @@ impl State {
                     //     new_pos.x, new_pos.y, defender.id
                     // );
                     defender.target_id = Some((i) as f32 / attacker.attacker_speed as f32);
+                    defender.target_attacker = Some(instance_id);
                     attacker.trigger_defender = true;
                 }
             }
@@ -244,7 +443,8 @@ impl State {
             coord_temp = coord;
         }
 
-        self.frame_no += 1;
+        self.update_frame_number(frame_no);
+        self.moved_in_frame.insert(instance_id);
 
         let attacker_result = Attacker {
             id: attacker.id,
@@ -262,6 +462,7 @@ impl State {
     pub fn spawn_hut_defender(
         &mut self,
         roads: &HashSet<(i32, i32)>,
+        instance_id: i32,
         attacker_current: Attacker,
     ) -> Option<Vec<DefenderDetails>> {
         let attacker = attacker_current.clone();
@@ -289,28 +490,24 @@ impl State {
                 let hut_triggered = self.hut.get(&hut_building.id).unwrap().hut_triggered;
 
                 //if hut is triggered and hut defenders are > 0, get the hut defender.
-                let time_elapsed = if let Some(time_stamp) = self
+                let spawn_due = if let Some(latest_frame) = self
                     .hut
                     .get(&hut_building.id)
                     .unwrap()
-                    .hut_defender_latest_time
+                    .hut_defender_latest_frame
                 {
-                    let start = SystemTime::now();
-                    let now = start
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Time went backwards");
-                    let time_interval = hut_building.frequency as u128;
-                    //check if time elapsed is greater than time stamp.
-                    now.as_millis() >= time_stamp + time_interval
+                    let frame_interval = millis_to_frames(hut_building.frequency);
+                    //check if enough frames have passed since the last spawn.
+                    self.frame_no >= latest_frame + frame_interval
                 } else {
                     true
                 };
                 if hut_triggered
                     && self.hut.get(&hut_building.id).unwrap().hut_defenders_count > 0
-                    && time_elapsed
+                    && spawn_due
                     && hut_building.current_hp > 0
                 {
-                    if let Some(hut_defender) = select_side_hut_defender(
+                    if let Some(mut hut_defender) = select_side_hut_defender(
                         &shadow_tiles,
                         roads,
                         &hut_building,
@@ -318,20 +515,17 @@ impl State {
                         &self.hut.get(&hut_building.id).unwrap().hut_defender,
                         i,
                     ) {
+                        hut_defender.target_attacker = Some(instance_id);
                         //push it to state.
                         self.defenders.push(hut_defender.clone());
                         //push it to frontend response.
                         response.push(hut_defender);
 
-                        //update time
-                        let start = SystemTime::now();
-                        let now = start
-                            .duration_since(UNIX_EPOCH)
-                            .expect("Time went backwards");
+                        //update spawn frame
                         self.hut
                             .get_mut(&hut_building.id)
                             .unwrap()
-                            .hut_defender_latest_time = Some(now.as_millis());
+                            .hut_defender_latest_frame = Some(self.frame_no);
 
                         //update hut_defenders count.
                         let curr_count =
@@ -349,42 +543,44 @@ impl State {
 
     pub fn place_bombs(
         &mut self,
+        frame_no: i32,
+        instance_id: i32,
+        bomb_id: i32,
         current_pos: Coords,
         bomb_position: Coords,
     ) -> Vec<BuildingResponse> {
-        // if attacker_current.bombs.len() - attacker.bombs.len() > 1 {
-
-        // }
+        let bombs_left = self.bombs.get(&bomb_id).map_or(0, |bomb| bomb.total_count);
+        if bombs_left <= 0 {
+            self.invalidate(frame_no, "Bomb Count forged");
+        }
 
-        if self.bombs.total_count <= 0 {
-            self.in_validation = InValidation {
-                message: "Bomb Count forged".to_string(),
-                is_invalidated: true,
-            };
+        // Each attacker can still only carry `amt_of_emps` bombs, whatever their types
+        let attacker_bombs_left = self
+            .attackers
+            .get(&instance_id)
+            .map_or(0, |attacker| attacker.bomb_count);
+        if attacker_bombs_left <= 0 {
+            self.invalidate(frame_no, "Bomb Count forged");
         }
 
-        if let Some(attacker) = &mut self.attacker {
+        if let Some(attacker) = self.attackers.get_mut(&instance_id) {
             attacker.bomb_count -= 1;
         }
 
         if current_pos.x != bomb_position.x || current_pos.y != bomb_position.y {
-            //GAME_OVER
-            println!("Bomb placed out of path");
-            self.in_validation = InValidation {
-                message: "Bomb placed out of path".to_string(),
-                is_invalidated: true,
-            };
+            self.invalidate(frame_no, "Bomb placed out of path");
         }
 
-        self.bomb_blast(bomb_position)
+        self.bomb_blast(bomb_id, bomb_position)
     }
 
     pub fn defender_movement(
         &mut self,
+        instance_id: i32,
         attacker_delta: Vec<Coords>,
         shortest_path: &HashMap<SourceDestXY, Coords>,
     ) -> DefenderReturnType {
-        let attacker = self.attacker.as_mut().unwrap();
+        let attacker = self.attackers.get_mut(&instance_id).unwrap();
         let mut defenders_damaged: Vec<DefenderResponse> = Vec::new();
 
         // if attacker is dead, no need to move the defenders
@@ -399,7 +595,10 @@ impl State {
         let mut collision_array: Vec<(usize, f32)> = Vec::new();
 
         for (index, defender) in self.defenders.iter_mut().enumerate() {
-            if !defender.is_alive || defender.target_id.is_none() {
+            if !defender.is_alive
+                || defender.target_id.is_none()
+                || defender.target_attacker != Some(instance_id)
+            {
                 continue;
             }
 
@@ -490,6 +689,7 @@ impl State {
         let mut attacker_death_time = 0.0; // frame fraction at which attacker dies
         for (index, time) in collision_array {
             self.defenders[index].target_id = None;
+            self.defenders[index].target_attacker = None;
             if time > 1.0 {
                 break;
             }
@@ -522,7 +722,7 @@ impl State {
         }
     }
 
-    pub fn mine_blast(&mut self, start_pos: Option<Coords>) -> Vec<MineDetails> {
+    pub fn mine_blast(&mut self, instance_id: i32, start_pos: Option<Coords>) -> Vec<MineDetails> {
         let mut damage_to_attacker;
         let attack_current_pos = start_pos.unwrap();
 
@@ -537,15 +737,17 @@ impl State {
                     radius: mine.radius,
                     damage: mine.damage,
                 });
-                self.mine_blast_update(mine.id, damage_to_attacker);
+                self.mine_blast_update(instance_id, mine.id, damage_to_attacker);
             }
         }
 
         triggered_mines
     }
 
-    pub fn bomb_blast(&mut self, bomb_position: Coords) -> Vec<BuildingResponse> {
-        let bomb = &mut self.bombs;
+    pub fn bomb_blast(&mut self, bomb_id: i32, bomb_position: Coords) -> Vec<BuildingResponse> {
+        let Some(bomb) = self.bombs.get(&bomb_id).cloned() else {
+            return Vec::new();
+        };
         let mut buildings_damaged: Vec<BuildingResponse> = Vec::new();
         for building in self.buildings.iter_mut() {
             if building.current_hp > 0 {
@@ -583,9 +785,10 @@ impl State {
                     if building.current_hp <= 0 {
                         building.current_hp = 0;
                         current_damage = old_hp;
-                        artifacts_taken_by_destroying_building =
-                            (building.artifacts_obtained as f32 * PERCENTANGE_ARTIFACTS_OBTAINABLE)
-                                .floor() as i32;
+                        artifacts_taken_by_destroying_building = lootable_artifacts(
+                            building.artifacts_obtained,
+                            building.loot_percentage,
+                        );
                         self.artifacts += artifacts_taken_by_destroying_building;
                         self.damage_percentage +=
                             (current_damage as f32 / self.total_hp_buildings as f32) * 100.0_f32;
@@ -606,7 +809,9 @@ impl State {
             }
         }
 
-        self.bombs.total_count -= 1;
+        if let Some(bomb) = self.bombs.get_mut(&bomb_id) {
+            bomb.total_count -= 1;
+        }
 
         buildings_damaged
     }
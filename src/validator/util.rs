@@ -2,7 +2,8 @@ use std::collections::HashSet;
 use std::hash::Hash;
 
 use crate::api::attack::socket::DefenderResponse;
-use crate::api::attack::socket::{ResultType, SocketResponse};
+use crate::api::attack::socket::{ResultType, SocketErrorCode, SocketResponse};
+use crate::constants::{DEFAULT_LOOT_PERCENTAGE, FRAME_DURATION_IN_MILLIS};
 use crate::validator::state::State;
 use serde::{Deserialize, Serialize};
 
@@ -50,6 +51,7 @@ pub struct DefenderDetails {
     pub is_alive: bool,
     pub damage_dealt: bool,
     pub target_id: Option<f32>,
+    pub target_attacker: Option<i32>, //instance id of the attacker being chased
     pub path_in_current_frame: Vec<Coords>,
     pub block_id: i32,
     pub level: i32,
@@ -60,7 +62,7 @@ pub struct HutDefenderDetails {
     pub hut_defender: DefenderDetails,
     pub hut_triggered: bool,
     pub hut_defenders_count: i32,
-    pub hut_defender_latest_time: Option<u128>,
+    pub hut_defender_latest_frame: Option<i32>,
 }
 
 // Structs for sending response
@@ -72,7 +74,7 @@ pub struct MineDetails {
     pub damage: i32,
 }
 
-#[derive(Serialize, Clone, Deserialize)]
+#[derive(Serialize, Clone, Deserialize, Debug)]
 pub struct BombType {
     pub id: i32,
     pub radius: i32,
@@ -92,6 +94,19 @@ pub struct BuildingDetails {
     pub range: i32,
     pub frequency: i32,
     pub block_id: i32,
+    #[serde(default = "default_loot_percentage")]
+    pub loot_percentage: i32,
+}
+
+pub fn default_loot_percentage() -> i32 {
+    DEFAULT_LOOT_PERCENTAGE
+}
+
+/// Artifacts taken by destroying a building that holds `artifacts`. The one loot rule, shared
+/// by `bomb_blast` and the preview sent in `init_attack`.
+pub fn lootable_artifacts(artifacts: i32, loot_percentage: i32) -> i32 {
+    // Banks hold up to i32::MAX, which a percentage would overflow
+    (artifacts.max(0) as i64 * loot_percentage as i64 / 100) as i32
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -100,6 +115,50 @@ pub struct InValidation {
     pub is_invalidated: bool,
 }
 
+/// What the server does once the validator flags a game.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ValidationPolicy {
+    // Record the flag and let the game go on
+    Warn,
+    // End the game, then settle it as usual
+    #[default]
+    StopGame,
+    // End the game and skip trophy and artifact settlement
+    VoidResult,
+}
+
+impl ValidationPolicy {
+    // VALIDATION_POLICY is one of "warn", "stop" or "void"; defaults to "stop"
+    pub fn from_env() -> ValidationPolicy {
+        match std::env::var("VALIDATION_POLICY").as_deref() {
+            Ok("warn") => ValidationPolicy::Warn,
+            Ok("void") => ValidationPolicy::VoidResult,
+            _ => ValidationPolicy::StopGame,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationPolicy::Warn => "warn",
+            ValidationPolicy::StopGame => "stop",
+            ValidationPolicy::VoidResult => "void",
+        }
+    }
+}
+
+/// `State::checksum` as of the end of `frame_number`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StateChecksum {
+    pub frame_number: i32,
+    pub value: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InValidationRecord {
+    pub frame_number: i32,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash, Copy, Deserialize)]
 pub struct Coords {
     pub x: i32,
@@ -130,10 +189,16 @@ pub struct ValidatorResponse {
     pub is_sync: bool,
 }
 
+// Rounds up, so a non-zero interval never collapses to zero frames
+pub fn millis_to_frames(millis: i32) -> i32 {
+    (millis + FRAME_DURATION_IN_MILLIS - 1) / FRAME_DURATION_IN_MILLIS
+}
+
 pub fn send_terminate_game_message(frame_number: i32, message: String) -> SocketResponse {
     SocketResponse {
         frame_number,
         result_type: ResultType::GameOver,
+        attacker_instance_id: None,
         is_alive: None,
         attacker_health: None,
         exploded_mines: None,
@@ -143,8 +208,119 @@ pub fn send_terminate_game_message(frame_number: i32, message: String) -> Socket
         hut_defenders: None,
         total_damage_percentage: None,
         is_sync: false,
+        remaining_time_in_millis: None,
+        error_code: None,
+        state_checksum: None,
+        state: None,
         is_game_over: true,
         message: Some(message),
+        result: None,
+    }
+}
+
+pub fn send_clock_sync_message(frame_number: i32, remaining_time_in_millis: u64) -> SocketResponse {
+    SocketResponse {
+        frame_number,
+        result_type: ResultType::ClockSync,
+        attacker_instance_id: None,
+        is_alive: None,
+        attacker_health: None,
+        exploded_mines: None,
+        defender_damaged: None,
+        damaged_buildings: None,
+        hut_triggered: false,
+        hut_defenders: None,
+        total_damage_percentage: None,
+        is_sync: false,
+        remaining_time_in_millis: Some(remaining_time_in_millis),
+        error_code: None,
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: None,
+        result: None,
+    }
+}
+
+pub fn send_error_message(
+    frame_number: i32,
+    error_code: SocketErrorCode,
+    message: String,
+) -> SocketResponse {
+    SocketResponse {
+        frame_number,
+        result_type: ResultType::Error,
+        attacker_instance_id: None,
+        is_alive: None,
+        attacker_health: None,
+        exploded_mines: None,
+        defender_damaged: None,
+        damaged_buildings: None,
+        hut_triggered: false,
+        hut_defenders: None,
+        total_damage_percentage: None,
+        is_sync: false,
+        remaining_time_in_millis: None,
+        error_code: Some(error_code),
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: Some(message),
+        result: None,
+    }
+}
+
+/// Tells a reconnected attacker the last frame the server acknowledged, so it can resume from it.
+pub fn send_resume_message(frame_number: i32, remaining_time_in_millis: u64) -> SocketResponse {
+    SocketResponse {
+        frame_number,
+        result_type: ResultType::GameResumed,
+        attacker_instance_id: None,
+        is_alive: None,
+        attacker_health: None,
+        exploded_mines: None,
+        defender_damaged: None,
+        damaged_buildings: None,
+        hut_triggered: false,
+        hut_defenders: None,
+        total_damage_percentage: None,
+        is_sync: false,
+        remaining_time_in_millis: Some(remaining_time_in_millis),
+        error_code: None,
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: Some(String::from("Game resumed")),
+        result: None,
+    }
+}
+
+/// Warns the attacker that the server is going down, and how long the game has left on it.
+pub fn send_shutdown_message(
+    frame_number: i32,
+    remaining_time_in_millis: u64,
+    message: String,
+) -> SocketResponse {
+    SocketResponse {
+        frame_number,
+        result_type: ResultType::ServerShutdown,
+        attacker_instance_id: None,
+        is_alive: None,
+        attacker_health: None,
+        exploded_mines: None,
+        defender_damaged: None,
+        damaged_buildings: None,
+        hut_triggered: false,
+        hut_defenders: None,
+        total_damage_percentage: None,
+        is_sync: false,
+        remaining_time_in_millis: Some(remaining_time_in_millis),
+        error_code: None,
+        state_checksum: None,
+        state: None,
+        is_game_over: false,
+        message: Some(message),
+        result: None,
     }
 }
 
@@ -195,3 +371,34 @@ pub fn select_side_hut_defender(
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loot_is_the_percentage_rounded_down() {
+        assert_eq!(lootable_artifacts(100, 30), 30);
+        assert_eq!(lootable_artifacts(99, 30), 29);
+        assert_eq!(lootable_artifacts(3, 30), 0);
+        assert_eq!(lootable_artifacts(100, default_loot_percentage()), 30);
+    }
+
+    #[test]
+    fn loot_covers_the_whole_range_of_percentages() {
+        assert_eq!(lootable_artifacts(100, 0), 0);
+        assert_eq!(lootable_artifacts(100, 100), 100);
+    }
+
+    #[test]
+    fn nothing_is_looted_from_an_empty_or_drifted_building() {
+        assert_eq!(lootable_artifacts(0, 30), 0);
+        assert_eq!(lootable_artifacts(-50, 30), 0);
+    }
+
+    #[test]
+    fn loot_of_a_full_bank_does_not_overflow() {
+        assert_eq!(lootable_artifacts(i32::MAX, 30), 644_245_094);
+        assert_eq!(lootable_artifacts(i32::MAX, 100), i32::MAX);
+    }
+}